@@ -0,0 +1,211 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Expr, ExprLit, Fields, GenericArgument, Lit, LitStr, Meta, PathArguments, Type};
+
+/// Implements `CommandOptions` for a struct with named fields.
+///
+/// Every field maps to one command option. `Option<T>` fields are optional options, every other
+/// field is required. Fields accept `#[option(name = "...", description = "...", autocomplete)]`,
+/// the name defaults to the field name and the description to the field's doc comment.
+#[proc_macro_derive(CommandOptions, attributes(option))]
+pub fn derive_command_options(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_command_options(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implements `FromOption` for a fieldless enum, turning it into a string option with choices.
+///
+/// Variants accept `#[choice(name = "...", value = "...")]`, the name defaults to the variant
+/// name and the value to the lowercased variant name.
+#[proc_macro_derive(CommandChoice, attributes(choice))]
+pub fn derive_command_choice(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_command_choice(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct OptionField {
+    ident: syn::Ident,
+    ty: Type,
+    name: String,
+    description: String,
+    autocomplete: bool,
+    required: bool,
+}
+
+fn expand_command_options(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(&input.ident, "CommandOptions requires named fields")),
+        },
+        _ => return Err(Error::new_spanned(&input.ident, "CommandOptions can only be derived for structs")),
+    };
+
+    let mut options = Vec::new();
+    for field in fields {
+        let ident = field.ident.clone().expect("Named field");
+        let mut name = ident.to_string().trim_start_matches("r#").to_string();
+        let mut description = doc_comment(&field.attrs);
+        let mut autocomplete = false;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("option")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.path.is_ident("description") {
+                    description = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("autocomplete") {
+                    autocomplete = true;
+                } else {
+                    return Err(meta.error("Unknown option attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        let (ty, required) = match option_inner(&field.ty) {
+            Some(inner) => (inner.clone(), false),
+            None => (field.ty.clone(), true),
+        };
+        options.push(OptionField {
+            ident,
+            ty,
+            description: description.unwrap_or_else(|| name.clone()),
+            name,
+            autocomplete,
+            required,
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let parse_fields = options.iter().map(|option| {
+        let OptionField { ident, name, required, .. } = option;
+        if *required {
+            quote!(#ident: ::worker_wasm_interactions_rs::options::required_option(data, #name)?)
+        } else {
+            quote!(#ident: ::worker_wasm_interactions_rs::options::optional_option(data, #name)?)
+        }
+    });
+    // Discord expects required options to be listed before optional ones.
+    let schema = options.iter().filter(|option| option.required)
+        .chain(options.iter().filter(|option| !option.required))
+        .map(|option| {
+            let OptionField { ty, name, description, autocomplete, required, .. } = option;
+            quote! {
+                <#ty as ::worker_wasm_interactions_rs::options::FromOption>::schema(
+                    #name.to_string(), #description.to_string(), #required, #autocomplete)
+            }
+        });
+
+    Ok(quote! {
+        impl #impl_generics ::worker_wasm_interactions_rs::options::CommandOptions for #ident #ty_generics #where_clause {
+            fn from_command_data(data: &::worker_wasm_interactions_rs::twilight_model::application::interaction::application_command::CommandData)
+                -> ::std::result::Result<Self, ::worker_wasm_interactions_rs::options::OptionError> {
+                Ok(Self {
+                    #(#parse_fields,)*
+                })
+            }
+
+            fn command_options() -> ::std::vec::Vec<::worker_wasm_interactions_rs::twilight_model::application::command::CommandOption> {
+                vec![#(#schema),*]
+            }
+        }
+    })
+}
+
+fn expand_command_choice(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let variants = match &input.data {
+        Data::Enum(data) => &data.variants,
+        _ => return Err(Error::new_spanned(&input.ident, "CommandChoice can only be derived for enums")),
+    };
+
+    let mut choices = Vec::new();
+    for variant in variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(variant, "CommandChoice variants must not have fields"));
+        }
+        let mut name = variant.ident.to_string();
+        let mut value = name.to_lowercase();
+        for attr in variant.attrs.iter().filter(|attr| attr.path().is_ident("choice")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.path.is_ident("value") {
+                    value = meta.value()?.parse::<LitStr>()?.value();
+                } else {
+                    return Err(meta.error("Unknown choice attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        choices.push((variant.ident.clone(), name, value));
+    }
+
+    let ident = &input.ident;
+    let arms = choices.iter().map(|(variant, _, value)| quote!(#value => Ok(Self::#variant)));
+    let pairs = choices.iter().map(|(_, name, value)| quote!((#name, #value)));
+
+    Ok(quote! {
+        impl ::worker_wasm_interactions_rs::options::FromOption for #ident {
+            fn from_option(
+                name: &str,
+                value: &::worker_wasm_interactions_rs::twilight_model::application::interaction::application_command::CommandOptionValue,
+                resolved: ::std::option::Option<&::worker_wasm_interactions_rs::twilight_model::application::interaction::application_command::CommandInteractionDataResolved>,
+            ) -> ::std::result::Result<Self, ::worker_wasm_interactions_rs::options::OptionError> {
+                let value = <::std::string::String as ::worker_wasm_interactions_rs::options::FromOption>::from_option(name, value, resolved)?;
+                match value.as_str() {
+                    #(#arms,)*
+                    _ => Err(::worker_wasm_interactions_rs::options::OptionError::InvalidChoice {
+                        name: name.to_string(),
+                        value,
+                    }),
+                }
+            }
+
+            fn schema(name: ::std::string::String, description: ::std::string::String, required: bool, _: bool)
+                -> ::worker_wasm_interactions_rs::twilight_model::application::command::CommandOption {
+                ::worker_wasm_interactions_rs::options::string_choice_schema(name, description, required, &[#(#pairs),*])
+            }
+        }
+    })
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs.iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(meta) => match &meta.value {
+                Expr::Lit(ExprLit { lit: Lit::Str(lit), .. }) => Some(lit.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
@@ -0,0 +1,268 @@
+use twilight_model::application::command::{Command, CommandOption, CommandType};
+use twilight_model::id::Id;
+use twilight_model::id::marker::GuildMarker;
+use crate::model::CommandBuilder;
+
+/// The difference between the defined commands and the commands known to Discord, by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandDiff {
+    /// Defined commands Discord doesn't know.
+    pub created: Vec<String>,
+    /// Commands whose definition differs from Discord's.
+    pub changed: Vec<String>,
+    /// Commands known to Discord that are not defined.
+    pub removed: Vec<String>,
+}
+
+impl CommandDiff {
+    pub fn between(defined: &[CommandBuilder], existing: &[Command]) -> Self {
+        let mut diff = CommandDiff::default();
+        for command in defined {
+            match existing.iter().find(|existing| existing.name == command.name && existing.kind == command.kind) {
+                None => diff.created.push(command.name.clone()),
+                Some(existing) if !command.matches(existing) => diff.changed.push(command.name.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = existing.iter()
+            .filter(|existing| !defined.iter().any(|command| command.name == existing.name && command.kind == existing.kind))
+            .map(|existing| existing.name.clone())
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Identifies a command by name and type, and for commands known to Discord by their guild.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRef {
+    pub name: String,
+    pub kind: CommandType,
+    /// The guild of a guild command, `None` for global commands and handlers.
+    pub guild_id: Option<Id<GuildMarker>>,
+}
+
+impl From<&Command> for CommandRef {
+    fn from(command: &Command) -> Self {
+        Self {
+            name: command.name.clone(),
+            kind: command.kind,
+            guild_id: command.guild_id,
+        }
+    }
+}
+
+/// Differences between the registered handlers and definitions and the commands known to Discord.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DriftReport {
+    /// Commands known to Discord without a registered handler.
+    pub missing_handlers: Vec<CommandRef>,
+    /// Registered handlers whose command is not known to Discord.
+    pub orphaned_handlers: Vec<CommandRef>,
+    /// Commands known to Discord whose schema differs from their definition.
+    pub schema_mismatches: Vec<CommandRef>,
+}
+
+impl DriftReport {
+    /// Compares the commands known to Discord with the `(type, path)` of every registered
+    /// handler and with the definitions. Chat input commands are compared by the path of each
+    /// sub command, such as `"config set"`, which a handler for the path or for one of its
+    /// parents like `"config"` takes. With a `fallback_handler` no command misses a handler.
+    pub fn between(handlers: &[(CommandType, &str)], fallback_handler: bool, defined: &[CommandBuilder], existing: &[Command]) -> Self {
+        let mut report = DriftReport::default();
+        for command in existing {
+            if !fallback_handler {
+                for path in invocable_paths(command) {
+                    if !handlers.iter().any(|(kind, handler)| *kind == command.kind && is_within(path.as_str(), handler)) {
+                        report.missing_handlers.push(CommandRef {
+                            name: path,
+                            kind: command.kind,
+                            guild_id: command.guild_id,
+                        });
+                    }
+                }
+            }
+            let definition = defined.iter()
+                .find(|definition| definition.name == command.name && definition.kind == command.kind);
+            if definition.is_some_and(|definition| !definition.matches(command)) {
+                report.schema_mismatches.push(CommandRef::from(command));
+            }
+        }
+        report.orphaned_handlers = handlers.iter()
+            .filter(|(kind, handler)| !existing.iter()
+                .filter(|command| command.kind == *kind)
+                .any(|command| invocable_paths(command).iter().any(|path| is_within(path, handler))))
+            .map(|(kind, handler)| CommandRef {
+                name: handler.to_string(),
+                kind: *kind,
+                guild_id: None,
+            })
+            .collect();
+        report.orphaned_handlers.sort_by(|a, b| a.name.cmp(&b.name).then(u8::from(a.kind).cmp(&u8::from(b.kind))));
+        report
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.missing_handlers.is_empty() && self.orphaned_handlers.is_empty() && self.schema_mismatches.is_empty()
+    }
+}
+
+/// Returns the paths `command` can be invoked by: its name, or the path of each of its sub
+/// commands such as `"admin roles add"`.
+fn invocable_paths(command: &Command) -> Vec<String> {
+    let mut paths = Vec::new();
+    collect_paths(command.name.clone(), &command.options, &mut paths);
+    paths
+}
+
+fn collect_paths(path: String, options: &[CommandOption], paths: &mut Vec<String>) {
+    let mut nested = options.iter().filter_map(|option| match option {
+        CommandOption::SubCommand(data) | CommandOption::SubCommandGroup(data) => Some(data),
+        _ => None
+    }).peekable();
+    if nested.peek().is_none() {
+        paths.push(path);
+        return;
+    }
+    for data in nested {
+        collect_paths(format!("{} {}", path, data.name), &data.options, paths);
+    }
+}
+
+/// Whether `path` is `parent` or one of the sub command paths below it.
+fn is_within(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent).is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::application::command::OptionsCommandOptionData;
+    use crate::options::FromOption;
+    use super::*;
+
+    fn definition(name: &str, kind: CommandType, description: &str) -> CommandBuilder {
+        CommandBuilder::new(name, description.to_string(), kind)
+    }
+
+    fn existing(definition: &CommandBuilder, guild_id: Option<u64>) -> Command {
+        Command {
+            application_id: Some(Id::new(1)),
+            default_member_permissions: definition.default_member_permissions,
+            dm_permission: Some(definition.dm_permission.unwrap_or(true)),
+            description: definition.description.clone(),
+            description_localizations: None,
+            guild_id: guild_id.map(Id::new),
+            id: Some(Id::new(2)),
+            kind: definition.kind,
+            name: definition.name.clone(),
+            name_localizations: None,
+            options: definition.options.clone(),
+            version: Id::new(3),
+        }
+    }
+
+    fn flag_option() -> CommandOption {
+        bool::schema("flag".to_string(), "A flag".to_string(), false, false)
+    }
+
+    #[test]
+    fn diff_lists_created_changed_and_removed() {
+        let ping = definition("ping", CommandType::ChatInput, "Pong");
+        let mut config = definition("config", CommandType::ChatInput, "Configures the bot");
+        let stale = definition("stale", CommandType::ChatInput, "Stale");
+        let info = definition("Info", CommandType::User, "");
+        let remote = [existing(&ping, None), existing(&config, None), existing(&stale, None)];
+        config.option(flag_option());
+
+        let diff = CommandDiff::between(&[ping, config, info], &remote);
+        assert_eq!(diff.created, vec!["Info".to_string()]);
+        assert_eq!(diff.changed, vec!["config".to_string()]);
+        assert_eq!(diff.removed, vec!["stale".to_string()]);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn diff_treats_discord_defaults_as_unchanged() {
+        let mut ping = definition("ping", CommandType::ChatInput, "Pong");
+        let mut remote = existing(&ping, None);
+        remote.name_localizations = Some(Default::default());
+        ping.option(flag_option());
+        remote.options = ping.options.clone();
+
+        assert!(CommandDiff::between(&[ping.clone()], &[remote.clone()]).is_empty());
+        ping.dm_permission(false);
+        assert_eq!(CommandDiff::between(&[ping], &[remote]).changed, vec!["ping".to_string()]);
+    }
+
+    #[test]
+    fn diff_distinguishes_command_types() {
+        let chat_input = definition("Info", CommandType::ChatInput, "Shows info");
+        let user = definition("Info", CommandType::User, "");
+        let diff = CommandDiff::between(&[chat_input], &[existing(&user, None)]);
+        assert_eq!(diff.created, vec!["Info".to_string()]);
+        assert_eq!(diff.removed, vec!["Info".to_string()]);
+    }
+
+    fn sub_command(name: &str) -> CommandOption {
+        CommandOption::SubCommand(OptionsCommandOptionData {
+            description: format!("Runs {}", name),
+            description_localizations: None,
+            name: name.to_string(),
+            name_localizations: None,
+            options: Vec::new(),
+        })
+    }
+
+    fn drift_ref(name: &str, kind: CommandType, guild_id: Option<u64>) -> CommandRef {
+        CommandRef {
+            name: name.to_string(),
+            kind,
+            guild_id: guild_id.map(Id::new),
+        }
+    }
+
+    #[test]
+    fn drift_reports_missing_orphaned_and_mismatched() {
+        let ping = definition("ping", CommandType::ChatInput, "Pong");
+        let mut config = definition("config", CommandType::ChatInput, "Configures the bot");
+        config.option(sub_command("get")).option(sub_command("set"));
+        let info = definition("Info", CommandType::User, "");
+        let legacy = definition("legacy", CommandType::ChatInput, "Legacy");
+        let remote = [existing(&ping, None), existing(&config, Some(4)), existing(&legacy, None)];
+        config.option(sub_command("reset"));
+
+        let handlers = [
+            (CommandType::User, "Info"),
+            (CommandType::ChatInput, "ping"),
+            (CommandType::ChatInput, "config set"),
+            (CommandType::ChatInput, "config delete"),
+        ];
+        let report = DriftReport::between(&handlers, false, &[ping, config, info], &remote);
+        assert_eq!(report.missing_handlers, vec![
+            drift_ref("config get", CommandType::ChatInput, Some(4)),
+            drift_ref("legacy", CommandType::ChatInput, None),
+        ]);
+        assert_eq!(report.orphaned_handlers, vec![
+            drift_ref("Info", CommandType::User, None),
+            drift_ref("config delete", CommandType::ChatInput, None),
+        ]);
+        assert_eq!(report.schema_mismatches, vec![drift_ref("config", CommandType::ChatInput, Some(4))]);
+        assert!(!report.is_empty());
+    }
+
+    #[test]
+    fn drift_accepts_parent_and_fallback_handlers() {
+        let mut config = definition("config", CommandType::ChatInput, "Configures the bot");
+        config.option(sub_command("get")).option(sub_command("set"));
+        let remote = [existing(&config, None)];
+        let defined = std::slice::from_ref(&config);
+        assert!(DriftReport::between(&[(CommandType::ChatInput, "config")], false, defined, &remote).is_empty());
+
+        let report = DriftReport::between(&[(CommandType::ChatInput, "config set")], true, defined, &remote);
+        assert!(report.is_empty());
+        assert!(!DriftReport::between(&[], false, defined, &remote).is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::{Display, Formatter};
+use twilight_model::application::command::CommandType;
+use twilight_model::application::interaction::InteractionType;
+use worker::Error;
+use worker::kv::KvError;
+use crate::model::MessageBuilder;
+use crate::options::OptionError;
+use crate::util::ToOwnedString;
+
+/// The error type of interaction handlers.
+///
+/// `User` errors are shown to the invoking user as they are, `Internal` errors only reach the
+/// error handler, which by default logs them and shows a generic message.
+#[derive(Debug)]
+pub enum InteractionError {
+    User(Box<MessageBuilder>),
+    Internal(Box<dyn std::error::Error>),
+}
+
+impl InteractionError {
+    pub fn user<S: ToOwnedString>(message: S) -> Self {
+        let mut builder = MessageBuilder::default();
+        builder.content(message);
+        InteractionError::User(Box::new(builder))
+    }
+
+    pub fn user_message<F: FnOnce(&mut MessageBuilder)>(message_builder: F) -> Self {
+        let mut builder = MessageBuilder::default();
+        message_builder(&mut builder);
+        InteractionError::User(Box::new(builder))
+    }
+
+    pub fn internal<E: Into<Box<dyn std::error::Error>>>(error: E) -> Self {
+        InteractionError::Internal(error.into())
+    }
+
+    pub fn is_user(&self) -> bool {
+        matches!(self, InteractionError::User(_))
+    }
+}
+
+impl Display for InteractionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InteractionError::User(builder) => write!(f, "{}", builder.content.as_deref().unwrap_or("User error")),
+            InteractionError::Internal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<Error> for InteractionError {
+    fn from(err: Error) -> Self {
+        InteractionError::Internal(Box::new(err))
+    }
+}
+
+impl From<KvError> for InteractionError {
+    fn from(err: KvError) -> Self {
+        InteractionError::Internal(Box::new(err))
+    }
+}
+
+impl From<serde_json::Error> for InteractionError {
+    fn from(err: serde_json::Error) -> Self {
+        InteractionError::Internal(Box::new(err))
+    }
+}
+
+impl From<reqwest::Error> for InteractionError {
+    fn from(err: reqwest::Error) -> Self {
+        InteractionError::Internal(Box::new(err))
+    }
+}
+
+impl From<PayloadError> for InteractionError {
+    fn from(err: PayloadError) -> Self {
+        InteractionError::Internal(Box::new(err))
+    }
+}
+
+/// Invalid options are caused by the invoking user, so they are shown to them.
+impl From<OptionError> for InteractionError {
+    fn from(err: OptionError) -> Self {
+        InteractionError::user(err.to_string())
+    }
+}
+
+/// Why a request with a valid signature could not be decoded into an interaction.
+#[derive(Debug)]
+pub enum PayloadError {
+    /// The body is not a valid interaction.
+    Json(serde_json::Error),
+    /// The interaction lacks the data its type requires.
+    MissingData(InteractionType),
+    /// The interaction carries data of a different type than its own.
+    UnexpectedData(InteractionType),
+    /// The context menu command lacks its target or the resolved data of it.
+    UnresolvedTarget(CommandType),
+}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadError::Json(err) => write!(f, "Invalid interaction body: {}", err),
+            PayloadError::MissingData(kind) => write!(f, "Missing data for {:?} interaction", kind),
+            PayloadError::UnexpectedData(kind) => write!(f, "Unexpected data for {:?} interaction", kind),
+            PayloadError::UnresolvedTarget(kind) => write!(f, "Unresolved target of {:?} command", kind),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
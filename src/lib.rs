@@ -1,104 +1,168 @@
 pub mod util;
 pub mod rest;
 pub mod model;
+pub mod options;
+pub mod pattern;
+pub mod middleware;
+pub mod error;
+pub mod commands;
 
 use std::collections::HashMap;
 use std::future::Future;
 use std::rc::Rc;
-use futures::future::LocalBoxFuture;
+use std::time::Duration;
+use futures::TryFutureExt;
+use futures::future::{self, Either, LocalBoxFuture, select};
+use twilight_model::application::command::CommandType;
 use twilight_model::application::interaction::{Interaction, InteractionData, InteractionType};
-use twilight_model::application::interaction::application_command::CommandData;
+use twilight_model::application::interaction::application_command::{CommandData, CommandDataOption, CommandOptionValue};
 use twilight_model::application::interaction::message_component::MessageComponentInteractionData;
+use twilight_model::application::interaction::modal::ModalInteractionData;
 use twilight_model::http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType};
 use twilight_model::id::Id;
 use twilight_model::id::marker::{GuildMarker, UserMarker};
 use worker::*;
 use crate::util::ToOwnedString;
-use crate::rest::Client;
+use crate::pattern::{CustomIdParam, CustomIdParams, PatternMatcher};
+use crate::rest::{Client, RestInteraction};
+use crate::middleware::{Middleware, Next};
+use crate::commands::{CommandDiff, DriftReport};
 
 pub use twilight_model;
+pub use crate::pattern::CustomIdPattern;
+pub use crate::error::{InteractionError, PayloadError};
+#[cfg(feature = "derive")]
+pub use worker_wasm_interactions_rs_derive::{CommandChoice, CommandOptions};
 use twilight_model::channel::message::MessageFlags;
+use ed25519_dalek::PublicKey;
 use worker::kv::KvStore;
-use crate::model::MessageBuilder;
-
-macro_rules! match_as {
-    ($obj:expr, $otype:path) => {
-        if let $otype(data) = $obj {
-            data
-        } else {
-            panic!("match_as! doesn't match!")
-        }
-    };
-}
+use crate::model::{CommandBuilder, MessageBuilder, MessageCommandData, UserCommandData};
 
 pub trait RouterExt {
     fn interactions(self, pattern: &str) -> Self;
 }
 
 pub trait GetInteractionData {
-    fn get_interactions(&self) -> &Interactions;
+    type State;
+
+    fn get_interactions(&self) -> &Interactions<Self::State>;
 }
 
-impl GetInteractionData for Interactions {
-    fn get_interactions(&self) -> &Interactions {
+impl<S> GetInteractionData for Interactions<S> {
+    type State = S;
+
+    fn get_interactions(&self) -> &Interactions<S> {
         self
     }
 }
 
-type InteractionResult = Result<InteractionResponse>;
+pub type InteractionResult = std::result::Result<InteractionResponse, InteractionError>;
 
-type InternalCommandHandler = Rc<dyn 'static + Fn(InteractionContext<Box<CommandData>>) -> LocalBoxFuture<'static, InteractionResult>>;
-pub type CommandHandler<T> = fn(InteractionContext<Box<CommandData>>) -> T;
+type InternalCommandHandler<S> = Rc<dyn 'static + Fn(InteractionContext<Box<CommandData>, S>) -> LocalBoxFuture<'static, InteractionResult>>;
+/// A plain function command handler. Registration also accepts closures of the same shape.
+pub type CommandHandler<T, S = ()> = fn(InteractionContext<Box<CommandData>, S>) -> T;
 
-type InternalComponentHandler = Rc<dyn 'static + Fn(InteractionContext<MessageComponentInteractionData>) -> LocalBoxFuture<'static, InteractionResult>>;
-pub type ComponentHandler<T> = fn(InteractionContext<MessageComponentInteractionData>) -> T;
+type InternalComponentHandler<S> = Rc<dyn 'static + Fn(InteractionContext<MessageComponentInteractionData, S>) -> LocalBoxFuture<'static, InteractionResult>>;
+/// A plain function component handler. Registration also accepts closures of the same shape.
+pub type ComponentHandler<T, S = ()> = fn(InteractionContext<MessageComponentInteractionData, S>) -> T;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
-pub struct CustomIdPattern {
-    starts_with: Option<String>,
-    equals: Option<String>,
-}
+type InternalUserCommandHandler<S> = Rc<dyn 'static + Fn(InteractionContext<UserCommandData, S>) -> LocalBoxFuture<'static, InteractionResult>>;
 
-impl CustomIdPattern {
-    pub fn starts_with<S: ToOwnedString>(pattern: S) -> Self {
-        Self {
-            starts_with: Some(pattern.to_owned_string()),
-            equals: None,
-        }
-    }
+type InternalMessageCommandHandler<S> = Rc<dyn 'static + Fn(InteractionContext<MessageCommandData, S>) -> LocalBoxFuture<'static, InteractionResult>>;
 
-    pub fn equals<S: ToOwnedString>(custom_id: S) -> Self {
-        Self {
-            starts_with: None,
-            equals: Some(custom_id.to_owned_string()),
-        }
-    }
+type RawHandler = Box<dyn 'static + Fn(serde_json::Value, Env) -> LocalBoxFuture<'static, std::result::Result<serde_json::Value, InteractionError>>>;
 
-    fn matches(&self, custom_id: &str) -> bool {
-        if let Some(pattern) = self.starts_with.as_ref() {
-            custom_id.starts_with(pattern)
-        } else if let Some(equals) = self.equals.as_ref() {
-            custom_id.eq(equals)
-        } else {
-            false
-        }
-    }
-}
+type PayloadErrorHook = Box<dyn 'static + Fn(&[u8], &PayloadError)>;
+
+type ErrorHandler<S> = Rc<dyn 'static + Fn(&InteractionError, &InteractionContext<(), S>) -> InteractionResponse>;
+
+type InternalModalHandler<S> = Rc<dyn 'static + Fn(InteractionContext<ModalInteractionData, S>) -> LocalBoxFuture<'static, InteractionResult>>;
+/// A plain function modal handler. Registration also accepts closures of the same shape.
+pub type ModalHandler<T, S = ()> = fn(InteractionContext<ModalInteractionData, S>) -> T;
 
-pub struct InteractionContext<D> {
+pub struct InteractionContext<D, S = ()> {
     pub raw: Interaction,
     pub data: D,
     pub rest: Client,
-    pub worker_env: Env
+    pub worker_env: Env,
+    worker_ctx: Option<Rc<Context>>,
+    params: CustomIdParams,
+    state: Rc<S>,
+    error_handler: ErrorHandler<S>,
 }
 
-impl<D> InteractionContext<D> {
-    fn create(interaction: Interaction, data: D, token: String, worker_env: Env) -> Self {
+impl<D, S> InteractionContext<D, S> {
+    fn create(interaction: Interaction, data: D, worker_env: Env, interactions: &Interactions<S>) -> Self {
         Self {
             raw: interaction,
             data,
-            rest: Client::new(token),
+            rest: Client::new(interactions.token.clone()),
             worker_env,
+            worker_ctx: interactions.worker_ctx.clone(),
+            params: CustomIdParams::new(),
+            state: interactions.state.clone(),
+            error_handler: interactions.error_handler.clone(),
+        }
+    }
+
+    /// Converts the interaction data, keeping everything else.
+    fn try_map_data<N, E, F>(self, map: F) -> std::result::Result<InteractionContext<N, S>, E>
+        where F: FnOnce(D) -> std::result::Result<N, E> {
+        Ok(InteractionContext {
+            raw: self.raw,
+            data: map(self.data)?,
+            rest: self.rest,
+            worker_env: self.worker_env,
+            worker_ctx: self.worker_ctx,
+            params: self.params,
+            state: self.state,
+            error_handler: self.error_handler,
+        })
+    }
+
+    /// Copies this context without its interaction data.
+    fn detached(&self) -> InteractionContext<(), S> {
+        InteractionContext {
+            raw: self.raw.clone(),
+            data: (),
+            rest: self.rest.clone(),
+            // Env only derefs to the cloneable JsValue
+            worker_env: self.worker_env.clone().into(),
+            worker_ctx: self.worker_ctx.clone(),
+            params: self.params.clone(),
+            state: self.state.clone(),
+            error_handler: self.error_handler.clone(),
+        }
+    }
+
+    /// Returns the state the [`Interactions`] were created with.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// Returns a value captured by a template [`CustomIdPattern`].
+    pub fn param(&self, name: &str) -> Option<&CustomIdParam> {
+        self.params.get(name)
+    }
+
+    pub fn param_str(&self, name: &str) -> Option<&str> {
+        match self.param(name)? {
+            CustomIdParam::Str(value) => Some(value.as_str()),
+            _ => None
+        }
+    }
+
+    pub fn param_u64(&self, name: &str) -> Option<u64> {
+        match self.param(name)? {
+            CustomIdParam::U64(value) => Some(*value),
+            _ => None
+        }
+    }
+
+    pub fn param_i64(&self, name: &str) -> Option<i64> {
+        match self.param(name)? {
+            CustomIdParam::I64(value) => Some(*value),
+            _ => None
         }
     }
 
@@ -110,7 +174,7 @@ impl<D> InteractionContext<D> {
         self.raw.author_id()
     }
 
-    pub fn followup<F: FnOnce(&mut MessageBuilder) -> ()>(&self, ephemeral: bool, message_builder: F) -> Result<InteractionResponse> {
+    pub fn followup<F: FnOnce(&mut MessageBuilder) -> ()>(&self, ephemeral: bool, message_builder: F) -> InteractionResult {
         let mut builder = MessageBuilder::default();
         message_builder(&mut builder);
         if ephemeral {
@@ -126,6 +190,60 @@ impl<D> InteractionContext<D> {
         })
     }
 
+    /// Responds with a modal. Fails if the title, custom_id or text input rows are missing.
+    pub fn modal<F: FnOnce(&mut MessageBuilder)>(&self, message_builder: F) -> InteractionResult {
+        let mut builder = MessageBuilder::default();
+        message_builder(&mut builder);
+        builder.validate_modal().map_err(InteractionError::internal)?;
+        Ok(InteractionResponse {
+            kind: InteractionResponseType::Modal,
+            data: Some(InteractionResponseData::from(builder))
+        })
+    }
+
+    pub fn autocomplete<F: FnOnce(&mut MessageBuilder)>(&self, message_builder: F) -> InteractionResult {
+        let mut builder = MessageBuilder::default();
+        message_builder(&mut builder);
+        if builder.choices.is_none() {
+            builder.choices = Some(Vec::new());
+        }
+        Ok(InteractionResponse {
+            kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+            data: Some(InteractionResponseData::from(builder))
+        })
+    }
+
+    /// Acknowledges the interaction with a loading message and runs `handler` in the background
+    /// through [`Context::wait_until`]. The message response returned by `handler` replaces the
+    /// loading message, other responses such as [`update_message`](InteractionContext::update_message)
+    /// go to the error handler instead. Requires [`Interactions::set_worker_context`].
+    pub fn defer<F, T, E>(self, ephemeral: bool, handler: F) -> InteractionResult
+        where D: 'static,
+              S: 'static,
+              F: 'static + FnOnce(InteractionContext<D, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        self.defer_as(InteractionResponseType::DeferredChannelMessageWithSource, ephemeral, handler)
+    }
+
+    fn defer_as<F, T, E>(self, kind: InteractionResponseType, ephemeral: bool, handler: F) -> InteractionResult
+        where D: 'static,
+              S: 'static,
+              F: 'static + FnOnce(InteractionContext<D, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let worker_ctx = self.worker_ctx.clone()
+            .ok_or_else(|| InteractionError::internal("Deferring requires a worker context, see Interactions::set_worker_context"))?;
+        let rest = self.rest.interaction(self.raw.application_id.get(), self.raw.token.clone(), ephemeral);
+        let error_context = self.detached();
+        complete_deferred(&worker_ctx, rest, error_context, kind, Box::pin(handler(self).map_err(Into::into)));
+        Ok(deferred_response(kind, ephemeral))
+    }
+
+    pub fn worker_context(&self) -> Option<&Context> {
+        self.worker_ctx.as_deref()
+    }
+
     // WORKER ENV BINDINGS
 
     pub fn secret(&self, binding: &str) -> Result<Secret> {
@@ -145,87 +263,825 @@ impl<D> InteractionContext<D> {
     }
 }
 
-pub struct Interactions {
-    public_key: String,
+impl<S> InteractionContext<Box<CommandData>, S> {
+    pub fn focused_option(&self) -> Option<&CommandDataOption> {
+        find_focused_option(&self.data.options)
+    }
+
+    pub fn focused_value(&self) -> Option<&str> {
+        self.focused_option().and_then(|option| match &option.value {
+            CommandOptionValue::Focused(value, _) => Some(value.as_str()),
+            _ => None
+        })
+    }
+}
+
+impl<S: 'static> InteractionContext<MessageComponentInteractionData, S> {
+    /// Responds by editing the message the component is attached to.
+    pub fn update_message<F: FnOnce(&mut MessageBuilder)>(&self, message_builder: F) -> InteractionResult {
+        let mut builder = MessageBuilder::default();
+        message_builder(&mut builder);
+        Ok(InteractionResponse {
+            kind: InteractionResponseType::UpdateMessage,
+            data: Some(InteractionResponseData::from(builder))
+        })
+    }
+
+    /// Acknowledges the component interaction without a visible response and runs `handler` in
+    /// the background. An [`update_message`](Self::update_message) response returned by `handler`
+    /// edits the message the component is attached to, a message response is posted as a followup,
+    /// as are the responses of the error handler. Requires [`Interactions::set_worker_context`].
+    pub fn defer_update<F, T, E>(self, handler: F) -> InteractionResult
+        where F: 'static + FnOnce(InteractionContext<MessageComponentInteractionData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        self.defer_as(InteractionResponseType::DeferredUpdateMessage, false, handler)
+    }
+}
+
+impl<S> InteractionContext<ModalInteractionData, S> {
+    /// Returns the submitted value of the text input with the given `custom_id`.
+    pub fn text_input_value(&self, custom_id: &str) -> Option<&str> {
+        self.text_input_values()
+            .find(|(id, _)| id.eq(&custom_id))
+            .map(|(_, value)| value)
+    }
+
+    /// Iterates all submitted text inputs as `(custom_id, value)` pairs.
+    pub fn text_input_values(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.data.components.iter()
+            .flat_map(|row| row.components.iter())
+            .filter_map(|component| component.value.as_deref()
+                .map(|value| (component.custom_id.as_str(), value)))
+    }
+}
+
+fn find_focused_option(options: &[CommandDataOption]) -> Option<&CommandDataOption> {
+    options.iter().find_map(|option| match &option.value {
+        CommandOptionValue::Focused(_, _) => Some(option),
+        CommandOptionValue::SubCommand(options) | CommandOptionValue::SubCommandGroup(options) => find_focused_option(options),
+        _ => None
+    })
+}
+
+fn deferred_response(kind: InteractionResponseType, ephemeral: bool) -> InteractionResponse {
+    let mut builder = MessageBuilder::default();
+    if ephemeral {
+        builder.flags = Some(MessageFlags::EPHEMERAL);
+    }
+    InteractionResponse {
+        kind,
+        data: Some(InteractionResponseData::from(builder))
+    }
+}
+
+/// Awaits `future` in the background and delivers its response according to the `deferral`
+/// the interaction was acknowledged with, see [`deferred_delivery`].
+fn complete_deferred<S: 'static>(worker_ctx: &Context, rest: RestInteraction, context: InteractionContext<(), S>,
+                                 deferral: InteractionResponseType, future: LocalBoxFuture<'static, InteractionResult>) {
+    worker_ctx.wait_until(async move {
+        let response = future.await
+            .and_then(|response| match deferred_delivery(deferral, &response) {
+                Ok(_) => Ok(response),
+                Err(reason) => Err(InteractionError::internal(reason))
+            })
+            .unwrap_or_else(|err| (context.error_handler)(&err, &context));
+        let delivered = match (deferred_delivery(deferral, &response), response.data) {
+            (Ok(Delivery::EditOriginal), Some(data)) => rest.edit_original_response(&data).await,
+            (Ok(Delivery::Followup), Some(data)) => rest.followup_response(&data).await,
+            (Err(reason), _) => {
+                console_log!("Interaction {} has no deferred response to deliver: {}", context.raw.id, reason);
+                return;
+            }
+            (Ok(_), None) => unreachable!("responses without data are never delivered"),
+        };
+        if let Err(err) = delivered {
+            console_log!("Failed to deliver deferred response: {}", err);
+        }
+    });
+}
+
+/// How the response of a deferred handler reaches the user.
+#[derive(Debug, PartialEq)]
+enum Delivery {
+    /// Edits the original response, the loading message or the message of the component.
+    EditOriginal,
+    /// Posts a new message.
+    Followup,
+}
+
+/// Chooses how `response` is delivered after acknowledging the interaction with `deferral`, or
+/// returns why it can't be delivered. Only message responses with data are deliverable; after a
+/// [`InteractionResponseType::DeferredUpdateMessage`] new messages are posted as a followup so
+/// they don't overwrite the message of the component.
+fn deferred_delivery(deferral: InteractionResponseType, response: &InteractionResponse) -> std::result::Result<Delivery, String> {
+    if response.data.is_none() {
+        return Err(format!("Deferred handler returned a {:?} response without data", response.kind));
+    }
+    match (deferral, response.kind) {
+        (InteractionResponseType::DeferredChannelMessageWithSource, InteractionResponseType::ChannelMessageWithSource)
+        | (InteractionResponseType::DeferredUpdateMessage, InteractionResponseType::UpdateMessage) => Ok(Delivery::EditOriginal),
+        (InteractionResponseType::DeferredUpdateMessage, InteractionResponseType::ChannelMessageWithSource) => Ok(Delivery::Followup),
+        (deferral, kind) => Err(format!("Deferred handler returned a {:?} response, which can't be delivered after a {:?}", kind, deferral)),
+    }
+}
+
+/// Returns the data of a command, autocomplete, component or modal interaction, checking that
+/// it matches the interaction's type and that context menu commands carry their target.
+fn interaction_data(interaction: &Interaction) -> std::result::Result<InteractionData, PayloadError> {
+    let data = interaction.data.clone().ok_or(PayloadError::MissingData(interaction.kind))?;
+    match (interaction.kind, &data) {
+        (InteractionType::ApplicationCommand, InteractionData::ApplicationCommand(command)) => {
+            match command.kind {
+                CommandType::User => {
+                    UserCommandData::resolve_target(command)?;
+                }
+                CommandType::Message => {
+                    MessageCommandData::resolve_target(command)?;
+                }
+                _ => {}
+            }
+            Ok(data)
+        }
+        (InteractionType::ApplicationCommandAutocomplete, InteractionData::ApplicationCommand(_))
+        | (InteractionType::MessageComponent, InteractionData::MessageComponent(_))
+        | (InteractionType::ModalSubmit, InteractionData::ModalSubmit(_)) => Ok(data),
+        _ => Err(PayloadError::UnexpectedData(interaction.kind))
+    }
+}
+
+/// The response to an interaction no registered or fallback handler takes.
+fn unhandled_response(kind: InteractionType) -> InteractionResponse {
+    match kind {
+        InteractionType::ApplicationCommandAutocomplete => util::autocomplete_result(Vec::new()),
+        InteractionType::MessageComponent => util::error_message("This message component is not registered".to_string()),
+        InteractionType::ModalSubmit => util::error_message("This modal is not registered".to_string()),
+        _ => util::error_message("This command is not registered".to_string()),
+    }
+}
+
+fn run_middlewares<'a, S: 'static>(middlewares: &'a [Rc<dyn Middleware<S>>], context: &'a InteractionContext<(), S>,
+                          endpoint: LocalBoxFuture<'a, InteractionResult>) -> LocalBoxFuture<'a, InteractionResult> {
+    match middlewares.split_first() {
+        Some((middleware, rest)) => middleware.handle(context, Next::new(run_middlewares(rest, context, endpoint))),
+        None => endpoint
+    }
+}
+
+/// Resolves the invoked command path, e.g. `"admin roles add"`, by walking the sub command
+/// (group) options. Every level is returned with the options nested below it, outermost first.
+fn command_paths(data: &CommandData) -> Vec<(String, Vec<CommandDataOption>)> {
+    let mut path = data.name.clone();
+    let mut levels = vec![(path.clone(), data.options.clone())];
+    let mut options = &data.options;
+    while let Some((name, nested)) = options.iter().find_map(|option| match &option.value {
+        CommandOptionValue::SubCommand(nested) | CommandOptionValue::SubCommandGroup(nested) => Some((&option.name, nested)),
+        _ => None
+    }) {
+        path.push(' ');
+        path.push_str(name);
+        levels.push((path.clone(), nested.clone()));
+        options = nested;
+    }
+    levels
+}
+
+/// Finds the handler of the most specific registered path of the invoked command, together
+/// with the options nested below that path.
+fn find_command_handler<'a, H>(handlers: &'a HashMap<&'static str, H>, data: &CommandData) -> Option<(&'a H, Vec<CommandDataOption>)> {
+    command_paths(data).into_iter().rev()
+        .find_map(|(path, options)| handlers.get(path.as_str())
+            .map(|handler| (handler, options)))
+}
+
+pub struct Interactions<S = ()> {
+    public_key: PublicKey,
     token: String,
-    app_command_handlers: HashMap<&'static str, InternalCommandHandler>,
-    msg_component_handlers: HashMap<CustomIdPattern, InternalComponentHandler>,
+    state: Rc<S>,
+    app_command_handlers: HashMap<&'static str, InternalCommandHandler<S>>,
+    user_command_handlers: HashMap<&'static str, InternalUserCommandHandler<S>>,
+    message_command_handlers: HashMap<&'static str, InternalMessageCommandHandler<S>>,
+    msg_component_handlers: PatternMatcher<InternalComponentHandler<S>>,
+    autocomplete_handlers: HashMap<(&'static str, Option<&'static str>), InternalCommandHandler<S>>,
+    modal_handlers: PatternMatcher<InternalModalHandler<S>>,
+    commands: Vec<CommandBuilder>,
+    fallback_command_handler: Option<InternalCommandHandler<S>>,
+    fallback_component_handler: Option<InternalComponentHandler<S>>,
+    middlewares: Vec<Rc<dyn Middleware<S>>>,
+    error_handler: ErrorHandler<S>,
+    worker_ctx: Option<Rc<Context>>,
+    auto_defer: Option<(Duration, bool)>,
+    max_request_age: Option<Duration>,
+    dedup_kv_binding: Option<String>,
+    payload_error_hook: Option<PayloadErrorHook>,
+    raw_handler: Option<RawHandler>,
 }
 
 impl Interactions {
-    pub fn new(public_key: String, token: String) -> Self {
-        Self {
-            public_key,
+    /// Creates the interactions, failing if `public_key` is not a hex encoded ed25519 public key.
+    pub fn new(public_key: String, token: String) -> Result<Self> {
+        Self::with_state(public_key, token, ())
+    }
+}
+
+impl<S: 'static> Interactions<S> {
+    /// Creates the interactions with a user defined `state`, available as
+    /// [`InteractionContext::state`] in every handler.
+    pub fn with_state(public_key: String, token: String, state: S) -> Result<Self> {
+        Ok(Self {
+            public_key: util::parse_public_key(public_key.as_str())?,
             token,
+            state: Rc::new(state),
             app_command_handlers: HashMap::new(),
-            msg_component_handlers: HashMap::new(),
+            user_command_handlers: HashMap::new(),
+            message_command_handlers: HashMap::new(),
+            msg_component_handlers: PatternMatcher::new(),
+            autocomplete_handlers: HashMap::new(),
+            modal_handlers: PatternMatcher::new(),
+            commands: Vec::new(),
+            fallback_command_handler: None,
+            fallback_component_handler: None,
+            middlewares: Vec::new(),
+            error_handler: Rc::new(util::default_error_response),
+            worker_ctx: None,
+            auto_defer: None,
+            max_request_age: None,
+            dedup_kv_binding: None,
+            payload_error_hook: None,
+            raw_handler: None,
+        })
+    }
+
+    /// Replaces the mapping of handler and middleware errors to responses, which defaults to
+    /// [`util::default_error_response`]. Also applies to deferred responses.
+    pub fn set_error_handler<F: 'static + Fn(&InteractionError, &InteractionContext<(), S>) -> InteractionResponse>(&mut self, error_handler: F) {
+        self.error_handler = Rc::new(error_handler);
+    }
+
+    /// Sets a hook receiving the raw body and the error of every request that carries a valid
+    /// signature but can't be decoded, unless the raw handler takes it. Such requests are always
+    /// logged and answered with a 400.
+    pub fn set_payload_error_hook<F: 'static + Fn(&[u8], &PayloadError)>(&mut self, hook: F) {
+        self.payload_error_hook = Some(Box::new(hook));
+    }
+
+    fn reject_payload(&self, body: &[u8], error: PayloadError) -> Result<Response> {
+        console_log!("Rejected malformed payload: {}", error);
+        if let Some(hook) = self.payload_error_hook.as_ref() {
+            hook(body, &error);
         }
+        Response::error("Malformed payload", 400)
     }
 
-    pub fn register_application_command_handler<T: 'static + Future<Output=Result<InteractionResponse>>>(&mut self, command_name: &'static str, handler: CommandHandler<T>) {
-        let internal_handler: InternalCommandHandler = Rc::new(move |ctx| Box::pin(handler(ctx)));
-        self.app_command_handlers.insert(command_name, internal_handler);
+    /// Registers a handler for interactions without typed handling: those that don't parse into
+    /// the typed model, such as new interaction types, and commands, autocompletes, components
+    /// and modals no registered or fallback handler takes. It receives the verified body and its
+    /// result is sent as the response body, bypassing the middlewares. Bodies that aren't JSON
+    /// are still rejected.
+    pub fn register_raw_handler<H, T, E>(&mut self, handler: H)
+        where H: 'static + Fn(serde_json::Value, Env) -> T,
+              T: 'static + Future<Output=std::result::Result<serde_json::Value, E>>,
+              E: 'static + Into<InteractionError> {
+        self.raw_handler = Some(Box::new(move |body, worker_env| Box::pin(handler(body, worker_env).map_err(Into::into))));
+    }
+
+    /// Passes an interaction without typed handling to the raw handler, `error` being why it
+    /// couldn't be handled typed, if it failed to decode.
+    async fn handle_raw(&self, body: &[u8], error: Option<PayloadError>, worker_env: Env) -> Result<Response> {
+        let handler = match self.raw_handler.as_ref() {
+            Some(handler) => handler,
+            None => return match error {
+                Some(error) => self.reject_payload(body, error),
+                None => Response::error("Missing implementation", 400)
+            }
+        };
+        let value = match serde_json::from_slice(body) {
+            Ok(value) => value,
+            Err(err) => return self.reject_payload(body, PayloadError::Json(err))
+        };
+        match handler(value, worker_env).await {
+            Ok(response) => Response::from_json(&response),
+            Err(err) => {
+                console_log!("Raw interaction handler failed: {}", err);
+                Response::error("Internal error", 500)
+            }
+        }
+    }
+
+    /// Adds a [`Middleware`] around the dispatch of every interaction except pings.
+    pub fn add_middleware<M: Middleware<S>>(&mut self, middleware: M) {
+        self.middlewares.push(Rc::new(middleware));
+    }
+
+    /// Defers command, component and modal interactions whose handler did not respond within
+    /// `budget`. The handler keeps running in the background and its response is delivered by
+    /// editing the deferred message, which is `ephemeral` if requested. Component interactions
+    /// are deferred as an update, so an update response edits the message the component is on
+    /// and a message response is posted as a followup. Responses that aren't messages, such as
+    /// modals, can't be delivered and go to the error handler. Requires
+    /// [`Interactions::set_worker_context`].
+    pub fn set_auto_defer(&mut self, budget: Duration, ephemeral: bool) {
+        self.auto_defer = Some((budget, ephemeral));
+    }
+
+    /// Rejects requests whose signed `x-signature-timestamp` is more than `max_age` away from the
+    /// current time, so captured requests can't be replayed later on.
+    pub fn set_max_request_age(&mut self, max_age: Duration) {
+        self.max_request_age = Some(max_age);
     }
 
-    pub fn register_message_component_handler<T: 'static + Future<Output=Result<InteractionResponse>>>(&mut self, custom_id: CustomIdPattern, handler: ComponentHandler<T>) {
-        let internal_handler: InternalComponentHandler = Rc::new(move |ctx| Box::pin(handler(ctx)));
-        self.msg_component_handlers.insert(custom_id, internal_handler);
+    /// Rejects interactions whose id was already received, remembering ids in the KV namespace
+    /// bound as `kv_binding`. As KV is eventually consistent, a replay reaching another location
+    /// within seconds may still pass, so combine it with [`Interactions::set_max_request_age`].
+    /// If the binding is missing or KV fails, requests are rejected rather than let through.
+    pub fn set_interaction_dedup<B: ToOwnedString>(&mut self, kv_binding: B) {
+        self.dedup_kv_binding = Some(kv_binding.to_owned_string());
     }
 
-    async fn handle_application_command(&self, context: InteractionContext<Box<CommandData>>) -> Result<Response> {
-        if let Some(handler) = self.app_command_handlers.get(context.data.name.as_str()) {
-            let result: InteractionResult = (handler)(context).await;
-            match result {
-                Ok(response) => Response::from_json(&response),
-                Err(err) => Response::from_json(&util::error_message(format!("An error occurred: {}", err.to_string())))
+    /// Returns why a request with a valid signature is rejected as a replay, if it is.
+    async fn replay_rejection(&self, req: &Request, body: &[u8], worker_env: &Env) -> Option<String> {
+        if let Some(max_age) = self.max_request_age {
+            let timestamp = req.headers().get("x-signature-timestamp").ok().flatten().unwrap_or_default();
+            if let Some(reason) = util::timestamp_rejection(timestamp.as_str(), Date::now().as_millis() / 1000, max_age) {
+                return Some(reason);
+            }
+        }
+        if let Some(binding) = self.dedup_kv_binding.as_ref() {
+            // bodies without an id fail to parse as an interaction later on
+            let id = serde_json::from_slice::<serde_json::Value>(body).ok()
+                .and_then(|body| body.get("id")?.as_str().map(|id| id.to_string()))?;
+            match self.record_interaction(binding, id.as_str(), worker_env).await {
+                Ok(false) => {}
+                Ok(true) => return Some(format!("Interaction {} was already received", id)),
+                // fail closed, a broken binding must not silently disable the replay protection
+                Err(err) => return Some(format!("Deduplication through KV binding `{}` failed: {}", binding, err)),
             }
+        }
+        None
+    }
+
+    /// Remembers the interaction `id` in the KV namespace `binding`, returning whether it was
+    /// already remembered.
+    async fn record_interaction(&self, binding: &str, id: &str, worker_env: &Env) -> Result<bool> {
+        let store = worker_env.kv(binding)?;
+        let key = format!("interaction-{}", id);
+        if store.get(key.as_str()).text().await?.is_some() {
+            return Ok(true);
+        }
+        // KV expires keys after 60 seconds at the earliest
+        let ttl = self.max_request_age.unwrap_or(Duration::from_secs(15 * 60)).as_secs().max(60);
+        store.put(key.as_str(), "")?.expiration_ttl(ttl).execute().await?;
+        Ok(false)
+    }
+
+    /// Runs `future`, the middlewares and handler of an interaction, deferring the interaction if
+    /// it doesn't finish within the auto defer budget.
+    async fn run_deferrable(&self, base: &InteractionContext<(), S>, future: LocalBoxFuture<'static, InteractionResult>) -> InteractionResult {
+        let (budget, ephemeral, worker_ctx) = match (self.auto_defer, self.worker_ctx.as_ref()) {
+            (Some((budget, ephemeral)), Some(worker_ctx)) => (budget, ephemeral, worker_ctx),
+            _ => return future.await
+        };
+        match select(future, Delay::from(budget)).await {
+            Either::Left((result, _)) => result,
+            Either::Right((_, future)) => {
+                let rest = base.rest.interaction(base.raw.application_id.get(), base.raw.token.clone(), ephemeral);
+                // components keep their message, which an update response of the handler then edits
+                let response = if base.raw.kind == InteractionType::MessageComponent {
+                    deferred_response(InteractionResponseType::DeferredUpdateMessage, false)
+                } else {
+                    deferred_response(InteractionResponseType::DeferredChannelMessageWithSource, ephemeral)
+                };
+                complete_deferred(worker_ctx, rest, base.detached(), response.kind, future);
+                Ok(response)
+            }
+        }
+    }
+
+    /// Provides the worker [`Context`] of the current request, required for deferred responses.
+    pub fn set_worker_context(&mut self, worker_ctx: Context) {
+        self.worker_ctx = Some(Rc::new(worker_ctx));
+    }
+
+    /// Registers a handler for a command or a sub command path such as `"config set"` or
+    /// `"admin roles add"`. Sub command handlers receive the innermost option list in `data.options`,
+    /// the most specific registered path wins.
+    pub fn register_application_command_handler<H, T, E>(&mut self, command_name: &'static str, handler: H)
+        where H: 'static + Fn(InteractionContext<Box<CommandData>, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalCommandHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.app_command_handlers.insert(command_name, internal_handler);
+    }
+
+    /// Registers a handler for the user context menu command `command_name`, receiving the
+    /// targeted user and, in guilds, their member.
+    pub fn register_user_command_handler<H, T, E>(&mut self, command_name: &'static str, handler: H)
+        where H: 'static + Fn(InteractionContext<UserCommandData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalUserCommandHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.user_command_handlers.insert(command_name, internal_handler);
+    }
+
+    /// Registers a handler for the message context menu command `command_name`, receiving the
+    /// targeted message.
+    pub fn register_message_command_handler<H, T, E>(&mut self, command_name: &'static str, handler: H)
+        where H: 'static + Fn(InteractionContext<MessageCommandData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalMessageCommandHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.message_command_handlers.insert(command_name, internal_handler);
+    }
+
+    /// Defines the chat input command `command_name` and registers its handler, replacing an
+    /// earlier definition of the same command. Discord requires a non-empty `description`.
+    pub fn register_command<D, F, H, T, E>(&mut self, command_name: &'static str, description: D, command_builder: F, handler: H)
+        where D: ToOwnedString,
+              F: FnOnce(&mut CommandBuilder),
+              H: 'static + Fn(InteractionContext<Box<CommandData>, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        self.define_command(command_name, description.to_owned_string(), CommandType::ChatInput, command_builder);
+        self.register_application_command_handler(command_name, handler);
+    }
+
+    /// Defines the user context menu command `command_name` and registers its handler.
+    pub fn register_user_command<F, H, T, E>(&mut self, command_name: &'static str, command_builder: F, handler: H)
+        where F: FnOnce(&mut CommandBuilder),
+              H: 'static + Fn(InteractionContext<UserCommandData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        self.define_command(command_name, String::new(), CommandType::User, command_builder);
+        self.register_user_command_handler(command_name, handler);
+    }
+
+    /// Defines the message context menu command `command_name` and registers its handler.
+    pub fn register_message_command<F, H, T, E>(&mut self, command_name: &'static str, command_builder: F, handler: H)
+        where F: FnOnce(&mut CommandBuilder),
+              H: 'static + Fn(InteractionContext<MessageCommandData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        self.define_command(command_name, String::new(), CommandType::Message, command_builder);
+        self.register_message_command_handler(command_name, handler);
+    }
+
+    fn define_command<F: FnOnce(&mut CommandBuilder)>(&mut self, command_name: &'static str, description: String, kind: CommandType, command_builder: F) {
+        let mut builder = CommandBuilder::new(command_name, description, kind);
+        command_builder(&mut builder);
+        self.commands.retain(|command| command.name != builder.name || command.kind != builder.kind);
+        self.commands.push(builder);
+    }
+
+    /// Returns the commands defined through [`Interactions::register_command`] and its context
+    /// menu counterparts, in definition order.
+    pub fn commands(&self) -> &[CommandBuilder] {
+        &self.commands
+    }
+
+    /// Overwrites the global commands of the application, or its commands in `guild_id`, with
+    /// the defined [`Interactions::commands`] and returns what changed on Discord's side.
+    pub async fn sync_commands(&self, application_id: u64, guild_id: Option<u64>) -> Result<CommandDiff> {
+        let client = Client::new(self.token.clone());
+        let existing = client.get_commands(&application_id, guild_id.as_ref()).await?;
+        let diff = CommandDiff::between(&self.commands, &existing);
+        client.bulk_overwrite_commands(&application_id, guild_id.as_ref(), &self.commands).await?;
+        Ok(diff)
+    }
+
+    /// Fetches the global commands of the application and its commands in each of `guild_ids`, and
+    /// compares them with the registered handlers and command definitions.
+    pub async fn detect_drift(&self, application_id: u64, guild_ids: &[u64]) -> Result<DriftReport> {
+        let client = Client::new(self.token.clone());
+        let mut existing = client.get_commands(&application_id, None).await?;
+        for guild_id in guild_ids {
+            existing.extend(client.get_commands(&application_id, Some(guild_id)).await?);
+        }
+        let handlers: Vec<(CommandType, &str)> = self.app_command_handlers.keys().map(|path| (CommandType::ChatInput, *path))
+            .chain(self.user_command_handlers.keys().map(|name| (CommandType::User, *name)))
+            .chain(self.message_command_handlers.keys().map(|name| (CommandType::Message, *name)))
+            .collect();
+        Ok(DriftReport::between(&handlers, self.fallback_command_handler.is_some(), &self.commands, &existing))
+    }
+
+    /// Registers a handler for message components matching `custom_id`. Exact patterns take
+    /// precedence, then the longest prefix, then registration order. Fails if the pattern is
+    /// ambiguous with an already registered one.
+    pub fn register_message_component_handler<H, T, E>(&mut self, custom_id: CustomIdPattern, handler: H) -> Result<()>
+        where H: 'static + Fn(InteractionContext<MessageComponentInteractionData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalComponentHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.msg_component_handlers.insert(custom_id, internal_handler)
+    }
+
+    /// Registers an autocomplete handler for `command_name`, which may be a sub command path. If `option_name` is given the handler
+    /// is only invoked while that option is focused, otherwise it serves every option of the command.
+    pub fn register_autocomplete_handler<H, T, E>(&mut self, command_name: &'static str, option_name: Option<&'static str>, handler: H)
+        where H: 'static + Fn(InteractionContext<Box<CommandData>, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalCommandHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.autocomplete_handlers.insert((command_name, option_name), internal_handler);
+    }
+
+    /// Registers a handler for modals matching `custom_id`, matched like message components.
+    pub fn register_modal_handler<H, T, E>(&mut self, custom_id: CustomIdPattern, handler: H) -> Result<()>
+        where H: 'static + Fn(InteractionContext<ModalInteractionData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalModalHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.modal_handlers.insert(custom_id, internal_handler)
+    }
+
+    /// Sets the handler for commands without a registered handler, which otherwise get an
+    /// ephemeral "not registered" message. It receives the unmodified command data.
+    pub fn set_fallback_command_handler<H, T, E>(&mut self, handler: H)
+        where H: 'static + Fn(InteractionContext<Box<CommandData>, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalCommandHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.fallback_command_handler = Some(internal_handler);
+    }
+
+    /// Sets the handler for message components no registered pattern matches, which otherwise
+    /// get an ephemeral "not registered" message.
+    pub fn set_fallback_component_handler<H, T, E>(&mut self, handler: H)
+        where H: 'static + Fn(InteractionContext<MessageComponentInteractionData, S>) -> T,
+              T: 'static + Future<Output=std::result::Result<InteractionResponse, E>>,
+              E: 'static + Into<InteractionError> {
+        let internal_handler: InternalComponentHandler<S> = Rc::new(move |ctx| Box::pin(handler(ctx).map_err(Into::into)));
+        self.fallback_component_handler = Some(internal_handler);
+    }
+
+    fn handle_application_command(&self, mut context: InteractionContext<Box<CommandData>, S>) -> Option<LocalBoxFuture<'static, InteractionResult>> {
+        match context.data.kind {
+            CommandType::User => if let Some(handler) = self.user_command_handlers.get(context.data.name.as_str()) {
+                return Some(match context.try_map_data(UserCommandData::try_from) {
+                    Ok(context) => (handler)(context),
+                    Err(err) => Box::pin(future::err(err.into()))
+                });
+            },
+            CommandType::Message => if let Some(handler) = self.message_command_handlers.get(context.data.name.as_str()) {
+                return Some(match context.try_map_data(MessageCommandData::try_from) {
+                    Ok(context) => (handler)(context),
+                    Err(err) => Box::pin(future::err(err.into()))
+                });
+            },
+            _ => {}
+        }
+        if let Some((handler, options)) = find_command_handler(&self.app_command_handlers, &context.data) {
+            context.data.options = options;
+            Some((handler)(context))
         } else {
-            Response::from_json(&util::error_message("This command is not registered".to_string()))
+            self.fallback_command_handler.as_ref().map(|handler| (handler)(context))
         }
     }
 
-    async fn handle_message_component(&self, context: InteractionContext<MessageComponentInteractionData>) -> Result<Response> {
-        if let Some(handler) = self.msg_component_handlers.iter()
-            .find(|(pattern, _)| pattern.matches(context.data.custom_id.as_str()))
-            .map(|(_, handler)| handler) {
-            let result: InteractionResult = (handler)(context).await;
-            match result {
-                Ok(response) => Response::from_json(&response),
-                Err(err) => Response::from_json(&util::error_message(format!("An error occurred: {}", err.to_string())))
+    /// Runs the middlewares around the handler of `interaction` and maps errors to a response
+    /// through the error handler. Auto defer races the whole chain, so a response that arrives
+    /// after the budget still passes through every middleware before it is delivered. Returns
+    /// `None` for interactions no handler takes if they are left to the raw handler.
+    async fn dispatch(&self, interaction: Interaction, data: InteractionData, worker_env: &Env) -> Option<InteractionResponse> {
+        // Env only derefs to the cloneable JsValue
+        let context = InteractionContext::create(interaction.clone(), (), (**worker_env).clone().into(), self);
+        let base = context.detached();
+        let kind = interaction.kind;
+        let autocomplete = kind == InteractionType::ApplicationCommandAutocomplete;
+        let endpoint = match self.route(interaction, data, (**worker_env).clone().into()) {
+            Some(endpoint) => endpoint,
+            None if self.raw_handler.is_some() => return None,
+            None => Box::pin(future::ok(unhandled_response(kind)))
+        };
+        let middlewares = self.middlewares.clone();
+        let chain: LocalBoxFuture<'static, InteractionResult> = Box::pin(async move {
+            run_middlewares(&middlewares, &context, endpoint).await
+        });
+        // autocomplete results can't be deferred
+        let result = if autocomplete {
+            chain.await
+        } else {
+            self.run_deferrable(&base, chain).await
+        };
+        Some(result.unwrap_or_else(|err| (self.error_handler)(&err, &base)))
+    }
+
+    /// Returns the handler future of the registered or fallback handler taking the interaction.
+    fn route(&self, interaction: Interaction, data: InteractionData, worker_env: Env) -> Option<LocalBoxFuture<'static, InteractionResult>> {
+        match data {
+            InteractionData::ApplicationCommand(command) if interaction.kind == InteractionType::ApplicationCommandAutocomplete => {
+                let context = InteractionContext::create(interaction, command, worker_env, self);
+                self.handle_autocomplete(context)
+            }
+            InteractionData::ApplicationCommand(command) => {
+                let context = InteractionContext::create(interaction, command, worker_env, self);
+                self.handle_application_command(context)
             }
+            InteractionData::MessageComponent(component) => {
+                let context = InteractionContext::create(interaction, component, worker_env, self);
+                self.handle_message_component(context)
+            }
+            InteractionData::ModalSubmit(modal) => {
+                let context = InteractionContext::create(interaction, modal, worker_env, self);
+                self.handle_modal_submit(context)
+            }
+            _ => Some(Box::pin(future::err(Error::from("Missing implementation").into())))
+        }
+    }
+
+    fn find_autocomplete_handler(&self, path: &str, option_name: Option<&str>) -> Option<&InternalCommandHandler<S>> {
+        self.autocomplete_handlers.iter()
+            .find(|((command, option), _)| command.eq(&path) && option.eq(&option_name))
+            .map(|(_, handler)| handler)
+    }
+
+    fn handle_autocomplete(&self, mut context: InteractionContext<Box<CommandData>, S>) -> Option<LocalBoxFuture<'static, InteractionResult>> {
+        let focused = context.focused_option().map(|option| option.name.clone());
+        let handler = command_paths(&context.data).into_iter().rev()
+            .find_map(|(path, options)| self.find_autocomplete_handler(path.as_str(), focused.as_deref())
+                .or_else(|| self.find_autocomplete_handler(path.as_str(), None))
+                .map(|handler| (handler, options)));
+        let (handler, options) = handler?;
+        context.data.options = options;
+        Some((handler)(context))
+    }
+
+    fn handle_message_component(&self, mut context: InteractionContext<MessageComponentInteractionData, S>) -> Option<LocalBoxFuture<'static, InteractionResult>> {
+        if let Some((pattern, handler)) = self.msg_component_handlers.find(context.data.custom_id.as_str()) {
+            match pattern.params(context.data.custom_id.as_str()) {
+                Ok(params) => context.params = params,
+                Err(err) => return Some(Box::pin(future::err(err.into())))
+            }
+            Some((handler)(context))
         } else {
-            Response::from_json(&util::error_message("This message component is not registered".to_string()))
+            self.fallback_component_handler.as_ref().map(|handler| (handler)(context))
         }
     }
+
+    fn handle_modal_submit(&self, mut context: InteractionContext<ModalInteractionData, S>) -> Option<LocalBoxFuture<'static, InteractionResult>> {
+        let (pattern, handler) = self.modal_handlers.find(context.data.custom_id.as_str())?;
+        match pattern.params(context.data.custom_id.as_str()) {
+            Ok(params) => context.params = params,
+            Err(err) => return Some(Box::pin(future::err(err.into())))
+        }
+        Some((handler)(context))
+    }
 }
 
-impl<'a, D: GetInteractionData + 'a> RouterExt for Router<'a, D> {
+impl<'a, D: GetInteractionData + 'a> RouterExt for Router<'a, D> where D::State: 'static {
     fn interactions(self, pattern: &str) -> Self {
         self.post_async(pattern, |mut req, ctx| async move {
             let body = req.bytes().await?;
             let interactions_lib = ctx.data.get_interactions();
-            if !util::validate_headers(&req, body.as_slice(), interactions_lib.public_key.as_str())? {
+            if !util::validate_headers(&req, body.as_slice(), &interactions_lib.public_key) {
                 return Response::error("Invalid token", 401);
             }
-            let interaction: Interaction = serde_json::from_slice(body.as_slice())?;
+            if let Some(reason) = interactions_lib.replay_rejection(&req, body.as_slice(), &ctx.env).await {
+                console_log!("Rejected request as a possible replay: {}", reason);
+                return Response::error("Invalid token", 401);
+            }
+            let interaction: Interaction = match serde_json::from_slice(body.as_slice()) {
+                Ok(interaction) => interaction,
+                Err(err) => return interactions_lib.handle_raw(body.as_slice(), Some(PayloadError::Json(err)), ctx.env).await
+            };
 
             match interaction.kind {
                 InteractionType::Ping => Response::from_json(&InteractionResponse {
                     kind: InteractionResponseType::Pong,
                     data: None,
                 }),
-                InteractionType::ApplicationCommand => {
-                    let command = match_as!(interaction.data.clone().expect("Missing data"), InteractionData::ApplicationCommand);
-                    let context = InteractionContext::create(interaction, command, interactions_lib.token.clone(), ctx.env);
-                    interactions_lib.handle_application_command(context).await
-                }
-                InteractionType::MessageComponent => {
-                    let component = match_as!(interaction.data.clone().expect("Missing data"), InteractionData::MessageComponent);
-                    let context = InteractionContext::create(interaction, component, interactions_lib.token.clone(), ctx.env);
-                    interactions_lib.handle_message_component(context).await
-                }
-                _ => Response::error("Missing implementation", 400)
+                InteractionType::ApplicationCommand
+                | InteractionType::ApplicationCommandAutocomplete
+                | InteractionType::MessageComponent
+                | InteractionType::ModalSubmit => match interaction_data(&interaction) {
+                    Ok(data) => match interactions_lib.dispatch(interaction, data, &ctx.env).await {
+                        Some(response) => Response::from_json(&response),
+                        None => interactions_lib.handle_raw(body.as_slice(), None, ctx.env).await
+                    },
+                    Err(err) => interactions_lib.handle_raw(body.as_slice(), Some(err), ctx.env).await
+                },
+                _ => interactions_lib.handle_raw(body.as_slice(), None, ctx.env).await
             }
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: InteractionResponseType) -> InteractionResponse {
+        InteractionResponse {
+            kind,
+            data: Some(InteractionResponseData::from(MessageBuilder::default())),
+        }
+    }
+
+    fn command_interaction(data: serde_json::Value) -> Interaction {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "application_id": "2",
+            "type": 2,
+            "token": "token",
+            "version": 1,
+            "data": data,
+        })).unwrap()
+    }
+
+    fn user_command(resolved: Option<serde_json::Value>) -> serde_json::Value {
+        serde_json::json!({
+            "id": "3",
+            "name": "inspect",
+            "type": 2,
+            "target_id": "4",
+            "resolved": resolved,
+        })
+    }
+
+    fn admin_roles_add() -> CommandData {
+        serde_json::from_value(serde_json::json!({
+            "id": "3",
+            "name": "admin",
+            "type": 1,
+            "options": [{
+                "name": "roles",
+                "type": 2,
+                "options": [{
+                    "name": "add",
+                    "type": 1,
+                    "options": [{ "name": "role", "type": 8, "value": "5" }],
+                }],
+            }],
+        })).unwrap()
+    }
+
+    #[test]
+    fn command_paths_walk_sub_command_groups() {
+        let paths = command_paths(&admin_roles_add());
+        let names = paths.iter().map(|(path, _)| path.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["admin", "admin roles", "admin roles add"]);
+        assert_eq!(paths[1].1[0].name, "add");
+        assert_eq!(paths[2].1[0].name, "role");
+    }
+
+    #[test]
+    fn most_specific_command_handler_wins() {
+        let data = admin_roles_add();
+        let mut handlers = HashMap::from([("admin", "admin"), ("admin roles", "admin roles")]);
+        let (handler, options) = find_command_handler(&handlers, &data).unwrap();
+        assert_eq!(*handler, "admin roles");
+        assert_eq!(options[0].name, "add");
+
+        handlers.insert("admin roles add", "admin roles add");
+        let (handler, options) = find_command_handler(&handlers, &data).unwrap();
+        assert_eq!(*handler, "admin roles add");
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].name, "role");
+
+        assert!(find_command_handler(&HashMap::from([("config", "config")]), &data).is_none());
+    }
+
+    #[test]
+    fn interaction_data_matches_the_interaction_type() {
+        let mut command = command_interaction(serde_json::json!({ "id": "3", "name": "ping", "type": 1 }));
+        assert!(matches!(interaction_data(&command), Ok(InteractionData::ApplicationCommand(_))));
+        // the model decodes data by type, so mismatches only come from constructed interactions
+        command.kind = InteractionType::MessageComponent;
+        assert!(matches!(interaction_data(&command), Err(PayloadError::UnexpectedData(InteractionType::MessageComponent))));
+        command.data = None;
+        assert!(matches!(interaction_data(&command), Err(PayloadError::MissingData(InteractionType::MessageComponent))));
+    }
+
+    #[test]
+    fn interaction_data_requires_resolved_context_menu_targets() {
+        let resolved = serde_json::json!({
+            "users": { "4": { "id": "4", "username": "target", "discriminator": "0001", "avatar": null } },
+        });
+        assert!(interaction_data(&command_interaction(user_command(Some(resolved)))).is_ok());
+        assert!(matches!(interaction_data(&command_interaction(user_command(None))), Err(PayloadError::UnresolvedTarget(CommandType::User))));
+        let message_command = serde_json::json!({ "id": "3", "name": "quote", "type": 3 });
+        assert!(matches!(interaction_data(&command_interaction(message_command)), Err(PayloadError::UnresolvedTarget(CommandType::Message))));
+    }
+
+    #[test]
+    fn deferred_update_posts_new_messages_as_followup() {
+        let deferral = InteractionResponseType::DeferredUpdateMessage;
+        assert_eq!(deferred_delivery(deferral, &message(InteractionResponseType::UpdateMessage)), Ok(Delivery::EditOriginal));
+        assert_eq!(deferred_delivery(deferral, &message(InteractionResponseType::ChannelMessageWithSource)), Ok(Delivery::Followup));
+    }
+
+    #[test]
+    fn deferred_message_rejects_update_responses() {
+        let deferral = InteractionResponseType::DeferredChannelMessageWithSource;
+        assert_eq!(deferred_delivery(deferral, &message(InteractionResponseType::ChannelMessageWithSource)), Ok(Delivery::EditOriginal));
+        assert!(deferred_delivery(deferral, &message(InteractionResponseType::UpdateMessage)).is_err());
+    }
+
+    #[test]
+    fn deferred_delivery_rejects_responses_without_message_data() {
+        let deferral = InteractionResponseType::DeferredChannelMessageWithSource;
+        let without_data = InteractionResponse { kind: InteractionResponseType::ChannelMessageWithSource, data: None };
+        assert!(deferred_delivery(deferral, &without_data).is_err());
+        assert!(deferred_delivery(deferral, &message(InteractionResponseType::Modal)).is_err());
+        assert!(deferred_delivery(InteractionResponseType::DeferredUpdateMessage, &message(InteractionResponseType::Modal)).is_err());
+    }
+}
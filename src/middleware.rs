@@ -0,0 +1,32 @@
+use futures::future::LocalBoxFuture;
+use crate::{InteractionContext, InteractionResult};
+
+/// Wraps the dispatch of every command, autocomplete, component and modal interaction.
+///
+/// `context.raw` holds the parsed interaction. A middleware either returns its own response
+/// without running `next`, or runs `next` and may inspect or rewrite the handler's result.
+/// Middlewares run in the order they were added, the first one being the outermost.
+///
+/// With [`Interactions::set_auto_defer`](crate::Interactions::set_auto_defer) the budget covers
+/// the whole chain: if it expires the chain keeps running in the background and its result,
+/// after passing back through every middleware, is delivered as the deferred response.
+pub trait Middleware<S>: 'static {
+    fn handle<'a>(&'a self, context: &'a InteractionContext<(), S>, next: Next<'a>) -> LocalBoxFuture<'a, InteractionResult>;
+}
+
+/// The remaining middlewares and the handler. Dropping it without running skips the handler.
+pub struct Next<'a> {
+    future: LocalBoxFuture<'a, InteractionResult>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(future: LocalBoxFuture<'a, InteractionResult>) -> Self {
+        Self {
+            future,
+        }
+    }
+
+    pub async fn run(self) -> InteractionResult {
+        self.future.await
+    }
+}
@@ -1,17 +1,25 @@
 use serde::{Serialize, Deserialize};
 use serde_with::skip_serializing_none;
-use twilight_model::application::command::CommandOptionChoice;
-use twilight_model::application::component::Component;
+use std::collections::HashMap;
+use twilight_model::application::command::{Command, CommandOption, CommandOptionChoice, CommandType};
+use twilight_model::application::component::{ActionRow, Component, TextInput};
+use twilight_model::application::component::text_input::TextInputStyle;
+use twilight_model::application::interaction::application_command::{CommandData, InteractionMember};
+use twilight_model::channel::Message;
 use twilight_model::channel::embed::Embed;
 use twilight_model::channel::message::{AllowedMentions, MessageFlags};
 use twilight_model::http::attachment::Attachment;
 use twilight_model::http::interaction::InteractionResponseData;
 use twilight_model::id::Id;
 use twilight_model::id::marker::{ChannelMarker, RoleMarker};
+use twilight_model::guild::Permissions;
+use twilight_model::user::User;
+use crate::error::PayloadError;
 use crate::ToOwnedString;
+use crate::options::CommandOptions;
 
 #[skip_serializing_none]
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct MessageBuilder {
     pub allowed_mentions: Option<AllowedMentions>,
     pub attachments: Option<Vec<Attachment>>,
@@ -26,11 +34,48 @@ pub struct MessageBuilder {
 }
 
 impl MessageBuilder {
+    pub fn choices(&mut self, choices: Vec<CommandOptionChoice>) -> &mut Self {
+        self.choices = Some(choices);
+        self
+    }
+
+    pub fn string_choice<N: ToOwnedString, V: ToOwnedString>(&mut self, name: N, value: V) -> &mut Self {
+        self.choices.get_or_insert_with(Vec::new).push(CommandOptionChoice::String {
+            name: name.to_owned_string(),
+            name_localizations: None,
+            value: value.to_owned_string(),
+        });
+        self
+    }
+
+    pub fn int_choice<N: ToOwnedString>(&mut self, name: N, value: i64) -> &mut Self {
+        self.choices.get_or_insert_with(Vec::new).push(CommandOptionChoice::Int {
+            name: name.to_owned_string(),
+            name_localizations: None,
+            value,
+        });
+        self
+    }
+
+    pub fn number_choice<N: ToOwnedString>(&mut self, name: N, value: f64) -> &mut Self {
+        self.choices.get_or_insert_with(Vec::new).push(CommandOptionChoice::Number {
+            name: name.to_owned_string(),
+            name_localizations: None,
+            value,
+        });
+        self
+    }
+
     pub fn content<S: ToOwnedString>(&mut self, content: S) -> &mut Self {
         self.content = Some(content.to_owned_string());
         self
     }
 
+    pub fn components(&mut self, components: Vec<Component>) -> &mut Self {
+        self.components = Some(components);
+        self
+    }
+
     pub fn custom_id<S: ToOwnedString>(&mut self, custom_id: S) -> &mut Self {
         self.custom_id = Some(custom_id.to_owned_string());
         self
@@ -40,6 +85,107 @@ impl MessageBuilder {
         self.title = Some(title.to_owned_string());
         self
     }
+
+    /// Appends an action row holding a single text input, as used by modals.
+    pub fn text_input<C: ToOwnedString, L: ToOwnedString, F: FnOnce(&mut TextInputBuilder)>(&mut self, custom_id: C, label: L, builder_fn: F) -> &mut Self {
+        let mut builder = TextInputBuilder::new(custom_id, label);
+        builder_fn(&mut builder);
+        self.components.get_or_insert_with(Vec::new).push(Component::ActionRow(ActionRow {
+            components: vec![Component::TextInput(builder.build())]
+        }));
+        self
+    }
+
+    pub(crate) fn validate_modal(&self) -> Result<(), String> {
+        if self.title.as_deref().unwrap_or_default().is_empty() {
+            return Err("Modal requires a title".to_string());
+        }
+        if self.custom_id.as_deref().unwrap_or_default().is_empty() {
+            return Err("Modal requires a custom_id".to_string());
+        }
+        let components = match self.components.as_ref() {
+            Some(components) if !components.is_empty() => components,
+            _ => return Err("Modal requires at least one action row".to_string())
+        };
+        for component in components {
+            match component {
+                Component::ActionRow(row) if !row.components.is_empty()
+                    && row.components.iter().all(|c| matches!(c, Component::TextInput(_))) => {}
+                _ => return Err("Modal components must be action rows of text inputs".to_string())
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct TextInputBuilder {
+    custom_id: String,
+    label: String,
+    style: TextInputStyle,
+    min_length: Option<u16>,
+    max_length: Option<u16>,
+    placeholder: Option<String>,
+    required: Option<bool>,
+    value: Option<String>,
+}
+
+impl TextInputBuilder {
+    fn new<C: ToOwnedString, L: ToOwnedString>(custom_id: C, label: L) -> Self {
+        Self {
+            custom_id: custom_id.to_owned_string(),
+            label: label.to_owned_string(),
+            style: TextInputStyle::Short,
+            min_length: None,
+            max_length: None,
+            placeholder: None,
+            required: None,
+            value: None,
+        }
+    }
+
+    pub fn style(&mut self, style: TextInputStyle) -> &mut Self {
+        self.style = style;
+        self
+    }
+
+    pub fn min_length(&mut self, min_length: u16) -> &mut Self {
+        self.min_length = Some(min_length);
+        self
+    }
+
+    pub fn max_length(&mut self, max_length: u16) -> &mut Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn placeholder<S: ToOwnedString>(&mut self, placeholder: S) -> &mut Self {
+        self.placeholder = Some(placeholder.to_owned_string());
+        self
+    }
+
+    pub fn required(&mut self, required: bool) -> &mut Self {
+        self.required = Some(required);
+        self
+    }
+
+    pub fn value<S: ToOwnedString>(&mut self, value: S) -> &mut Self {
+        self.value = Some(value.to_owned_string());
+        self
+    }
+
+    fn build(self) -> TextInput {
+        TextInput {
+            custom_id: self.custom_id,
+            label: self.label,
+            max_length: self.max_length,
+            min_length: self.min_length,
+            placeholder: self.placeholder,
+            required: self.required,
+            style: self.style,
+            value: self.value,
+        }
+    }
 }
 
 #[skip_serializing_none]
@@ -72,6 +218,99 @@ impl MemberEditBuilder {
     }
 }
 
+/// The definition of an application command as it is registered with Discord.
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandBuilder {
+    pub name: String,
+    pub name_localizations: Option<HashMap<String, String>>,
+    pub description: String,
+    pub description_localizations: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub options: Vec<CommandOption>,
+    pub default_member_permissions: Option<Permissions>,
+    pub dm_permission: Option<bool>,
+    #[serde(rename = "type")]
+    pub kind: CommandType,
+}
+
+impl CommandBuilder {
+    pub(crate) fn new<S: ToOwnedString>(name: S, description: String, kind: CommandType) -> Self {
+        Self {
+            name: name.to_owned_string(),
+            name_localizations: None,
+            description,
+            description_localizations: None,
+            options: Vec::new(),
+            default_member_permissions: None,
+            dm_permission: None,
+            kind,
+        }
+    }
+
+    /// Replaces the description. Chat input commands require one, context menu commands must
+    /// leave it empty.
+    pub fn description<S: ToOwnedString>(&mut self, description: S) -> &mut Self {
+        self.description = description.to_owned_string();
+        self
+    }
+
+    pub fn name_localization<L: ToOwnedString, S: ToOwnedString>(&mut self, locale: L, name: S) -> &mut Self {
+        self.name_localizations.get_or_insert_with(HashMap::new)
+            .insert(locale.to_owned_string(), name.to_owned_string());
+        self
+    }
+
+    pub fn description_localization<L: ToOwnedString, S: ToOwnedString>(&mut self, locale: L, description: S) -> &mut Self {
+        self.description_localizations.get_or_insert_with(HashMap::new)
+            .insert(locale.to_owned_string(), description.to_owned_string());
+        self
+    }
+
+    pub fn option(&mut self, option: CommandOption) -> &mut Self {
+        self.options.push(option);
+        self
+    }
+
+    pub fn options(&mut self, options: Vec<CommandOption>) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    /// Uses the options declared by a [`CommandOptions`] type.
+    pub fn options_from<T: CommandOptions>(&mut self) -> &mut Self {
+        self.options = T::command_options();
+        self
+    }
+
+    pub fn default_member_permissions(&mut self, permissions: Permissions) -> &mut Self {
+        self.default_member_permissions = Some(permissions);
+        self
+    }
+
+    pub fn dm_permission(&mut self, dm_permission: bool) -> &mut Self {
+        self.dm_permission = Some(dm_permission);
+        self
+    }
+
+    /// Whether `command`, as returned by Discord, is the same command with the same schema.
+    /// Unset fields are compared with Discord's defaults.
+    pub fn matches(&self, command: &Command) -> bool {
+        self.name == command.name
+            && self.kind == command.kind
+            && self.description == command.description
+            && self.options == command.options
+            && localizations(&self.name_localizations) == localizations(&command.name_localizations)
+            && localizations(&self.description_localizations) == localizations(&command.description_localizations)
+            && self.default_member_permissions == command.default_member_permissions
+            && self.dm_permission.unwrap_or(true) == command.dm_permission.unwrap_or(true)
+    }
+}
+
+fn localizations(localizations: &Option<HashMap<String, String>>) -> Option<&HashMap<String, String>> {
+    localizations.as_ref().filter(|localizations| !localizations.is_empty())
+}
+
 impl From<MessageBuilder> for InteractionResponseData {
     fn from(builder: MessageBuilder) -> Self {
         Self {
@@ -87,4 +326,127 @@ impl From<MessageBuilder> for InteractionResponseData {
             tts: builder.tts,
         }
     }
-}
\ No newline at end of file
+}
+
+/// The data of a user context menu command together with its resolved target.
+#[derive(Debug, Clone)]
+pub struct UserCommandData {
+    pub command: Box<CommandData>,
+    pub user: User,
+    /// The target's guild member, only present when invoked in a guild.
+    pub member: Option<InteractionMember>,
+}
+
+impl UserCommandData {
+    /// Looks up the targeted user and, in guilds, their member in the resolved data of `command`.
+    pub(crate) fn resolve_target(command: &CommandData) -> Result<(&User, Option<&InteractionMember>), PayloadError> {
+        let target_id = command.target_id
+            .ok_or(PayloadError::UnresolvedTarget(CommandType::User))?
+            .cast();
+        let resolved = command.resolved.as_ref()
+            .ok_or(PayloadError::UnresolvedTarget(CommandType::User))?;
+        let user = resolved.users.get(&target_id)
+            .ok_or(PayloadError::UnresolvedTarget(CommandType::User))?;
+        Ok((user, resolved.members.get(&target_id)))
+    }
+}
+
+impl TryFrom<Box<CommandData>> for UserCommandData {
+    type Error = PayloadError;
+
+    fn try_from(command: Box<CommandData>) -> Result<Self, Self::Error> {
+        let (user, member) = Self::resolve_target(&command)?;
+        let (user, member) = (user.clone(), member.cloned());
+        Ok(Self {
+            command,
+            user,
+            member,
+        })
+    }
+}
+
+/// The data of a message context menu command together with its resolved target.
+#[derive(Debug, Clone)]
+pub struct MessageCommandData {
+    pub command: Box<CommandData>,
+    pub message: Message,
+}
+
+impl MessageCommandData {
+    /// Looks up the targeted message in the resolved data of `command`.
+    pub(crate) fn resolve_target(command: &CommandData) -> Result<&Message, PayloadError> {
+        let target_id = command.target_id
+            .ok_or(PayloadError::UnresolvedTarget(CommandType::Message))?
+            .cast();
+        command.resolved.as_ref()
+            .and_then(|resolved| resolved.messages.get(&target_id))
+            .ok_or(PayloadError::UnresolvedTarget(CommandType::Message))
+    }
+}
+
+impl TryFrom<Box<CommandData>> for MessageCommandData {
+    type Error = PayloadError;
+
+    fn try_from(command: Box<CommandData>) -> Result<Self, Self::Error> {
+        let message = Self::resolve_target(&command)?.clone();
+        Ok(Self {
+            command,
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::application::component::Button;
+    use twilight_model::application::component::button::ButtonStyle;
+    use super::*;
+
+    fn modal() -> MessageBuilder {
+        let mut builder = MessageBuilder::default();
+        builder.title("Feedback")
+            .custom_id("feedback")
+            .text_input("comment", "Comment", |_| {});
+        builder
+    }
+
+    #[test]
+    fn accepts_complete_modal() {
+        assert_eq!(modal().validate_modal(), Ok(()));
+    }
+
+    #[test]
+    fn modal_requires_title_and_custom_id() {
+        let mut builder = modal();
+        builder.title = None;
+        assert!(builder.validate_modal().is_err());
+
+        let mut builder = modal();
+        builder.custom_id("");
+        assert!(builder.validate_modal().is_err());
+    }
+
+    #[test]
+    fn modal_requires_rows_of_text_inputs() {
+        let mut builder = modal();
+        builder.components(Vec::new());
+        assert!(builder.validate_modal().is_err());
+
+        let mut builder = modal();
+        builder.components(vec![Component::ActionRow(ActionRow { components: Vec::new() })]);
+        assert!(builder.validate_modal().is_err());
+
+        let mut builder = modal();
+        builder.components.get_or_insert_with(Vec::new).push(Component::ActionRow(ActionRow {
+            components: vec![Component::Button(Button {
+                custom_id: Some("submit".to_string()),
+                disabled: false,
+                emoji: None,
+                label: Some("Submit".to_string()),
+                style: ButtonStyle::Primary,
+                url: None,
+            })]
+        }));
+        assert!(builder.validate_modal().is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::fmt::{Display, Formatter};
+use twilight_model::application::command::{BaseCommandOptionData, ChannelCommandOptionData, ChoiceCommandOptionData, CommandOption, CommandOptionChoice, CommandOptionType, NumberCommandOptionData};
+use twilight_model::application::interaction::application_command::{CommandData, CommandInteractionDataResolved, CommandOptionValue, InteractionChannel};
+use twilight_model::channel::Attachment;
+use twilight_model::guild::Role;
+use twilight_model::user::User;
+use worker::Error;
+use crate::InteractionContext;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    /// The option was not supplied by the user.
+    Missing { name: String },
+    /// The option was supplied with a different type than requested.
+    Mistyped { name: String, expected: CommandOptionType, found: CommandOptionType },
+    /// The option references an entity that is missing from `CommandData::resolved`.
+    Unresolved { name: String },
+    /// The option value is not one of the declared choices.
+    InvalidChoice { name: String, value: String },
+}
+
+impl OptionError {
+    pub fn name(&self) -> &str {
+        match self {
+            OptionError::Missing { name } => name,
+            OptionError::Mistyped { name, .. } => name,
+            OptionError::Unresolved { name } => name,
+            OptionError::InvalidChoice { name, .. } => name,
+        }
+    }
+}
+
+impl Display for OptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionError::Missing { name } => write!(f, "Missing option `{}`", name),
+            OptionError::Mistyped { name, expected, found } =>
+                write!(f, "Option `{}` has type {} but {} was expected", name, found.kind(), expected.kind()),
+            OptionError::Unresolved { name } => write!(f, "Option `{}` could not be resolved", name),
+            OptionError::InvalidChoice { name, value } => write!(f, "Option `{}` has no choice `{}`", name, value),
+        }
+    }
+}
+
+impl std::error::Error for OptionError {}
+
+impl From<OptionError> for Error {
+    fn from(err: OptionError) -> Self {
+        Error::RustError(err.to_string())
+    }
+}
+
+impl<S> InteractionContext<Box<CommandData>, S> {
+    /// Parses the options of this command into a type deriving [`CommandOptions`].
+    pub fn parse_options<T: CommandOptions>(&self) -> Result<T, OptionError> {
+        T::from_command_data(&self.data)
+    }
+
+    pub fn option(&self, name: &str) -> Option<&CommandOptionValue> {
+        self.data.options.iter()
+            .find(|option| option.name.eq(name))
+            .map(|option| &option.value)
+    }
+
+    fn require_option(&self, name: &str) -> Result<&CommandOptionValue, OptionError> {
+        self.option(name).ok_or_else(|| OptionError::Missing { name: name.to_string() })
+    }
+
+    pub fn option_str(&self, name: &str) -> Result<&str, OptionError> {
+        match self.require_option(name)? {
+            CommandOptionValue::String(value) => Ok(value.as_str()),
+            other => Err(mistyped(name, CommandOptionType::String, other)),
+        }
+    }
+
+    pub fn option_i64(&self, name: &str) -> Result<i64, OptionError> {
+        match self.require_option(name)? {
+            CommandOptionValue::Integer(value) => Ok(*value),
+            other => Err(mistyped(name, CommandOptionType::Integer, other)),
+        }
+    }
+
+    pub fn option_bool(&self, name: &str) -> Result<bool, OptionError> {
+        match self.require_option(name)? {
+            CommandOptionValue::Boolean(value) => Ok(*value),
+            other => Err(mistyped(name, CommandOptionType::Boolean, other)),
+        }
+    }
+
+    pub fn option_user(&self, name: &str) -> Result<&User, OptionError> {
+        User::resolve(name, self.require_option(name)?, self.data.resolved.as_ref())
+    }
+
+    pub fn option_role(&self, name: &str) -> Result<&Role, OptionError> {
+        Role::resolve(name, self.require_option(name)?, self.data.resolved.as_ref())
+    }
+
+    pub fn option_channel(&self, name: &str) -> Result<&InteractionChannel, OptionError> {
+        InteractionChannel::resolve(name, self.require_option(name)?, self.data.resolved.as_ref())
+    }
+
+    pub fn option_attachment(&self, name: &str) -> Result<&Attachment, OptionError> {
+        Attachment::resolve(name, self.require_option(name)?, self.data.resolved.as_ref())
+    }
+}
+
+/// Entities options reference by id, which Discord includes in `CommandData::resolved`.
+trait Resolvable {
+    fn resolve<'a>(name: &str, value: &CommandOptionValue, resolved: Option<&'a CommandInteractionDataResolved>) -> Result<&'a Self, OptionError>;
+}
+
+macro_rules! resolvable {
+    ($ty:ty, $kind:ident, $field:ident) => {
+        impl Resolvable for $ty {
+            fn resolve<'a>(name: &str, value: &CommandOptionValue, resolved: Option<&'a CommandInteractionDataResolved>) -> Result<&'a Self, OptionError> {
+                match value {
+                    CommandOptionValue::$kind(id) => resolved
+                        .and_then(|resolved| resolved.$field.get(id))
+                        .ok_or_else(|| OptionError::Unresolved { name: name.to_string() }),
+                    other => Err(mistyped(name, CommandOptionType::$kind, other)),
+                }
+            }
+        }
+    };
+}
+
+resolvable!(User, User, users);
+resolvable!(Role, Role, roles);
+resolvable!(InteractionChannel, Channel, channels);
+resolvable!(Attachment, Attachment, attachments);
+
+fn mistyped(name: &str, expected: CommandOptionType, found: &CommandOptionValue) -> OptionError {
+    OptionError::Mistyped {
+        name: name.to_string(),
+        expected,
+        found: found.kind(),
+    }
+}
+
+/// A set of command options that can be parsed from [`CommandData`] and describes its own schema.
+///
+/// Usually implemented through `#[derive(CommandOptions)]`.
+pub trait CommandOptions: Sized {
+    fn from_command_data(data: &CommandData) -> Result<Self, OptionError>;
+
+    fn command_options() -> Vec<CommandOption>;
+}
+
+/// A single option value, together with the schema of the option it is read from.
+pub trait FromOption: Sized {
+    fn from_option(name: &str, value: &CommandOptionValue, resolved: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError>;
+
+    fn schema(name: String, description: String, required: bool, autocomplete: bool) -> CommandOption;
+}
+
+pub fn required_option<T: FromOption>(data: &CommandData, name: &str) -> Result<T, OptionError> {
+    optional_option(data, name)?.ok_or_else(|| OptionError::Missing { name: name.to_string() })
+}
+
+pub fn optional_option<T: FromOption>(data: &CommandData, name: &str) -> Result<Option<T>, OptionError> {
+    data.options.iter()
+        .find(|option| option.name.eq(name))
+        .map(|option| T::from_option(name, &option.value, data.resolved.as_ref()))
+        .transpose()
+}
+
+/// Builds the schema of a string option restricted to the given `(name, value)` choices.
+pub fn string_choice_schema(name: String, description: String, required: bool, choices: &[(&str, &str)]) -> CommandOption {
+    CommandOption::String(ChoiceCommandOptionData {
+        choices: choices.iter()
+            .map(|(choice_name, value)| CommandOptionChoice::String {
+                name: choice_name.to_string(),
+                name_localizations: None,
+                value: value.to_string(),
+            })
+            .collect(),
+        description,
+        name,
+        required,
+        ..Default::default()
+    })
+}
+
+fn base_data(name: String, description: String, required: bool) -> BaseCommandOptionData {
+    BaseCommandOptionData {
+        description,
+        description_localizations: None,
+        name,
+        name_localizations: None,
+        required,
+    }
+}
+
+impl FromOption for String {
+    fn from_option(name: &str, value: &CommandOptionValue, _: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        match value {
+            CommandOptionValue::String(value) => Ok(value.clone()),
+            other => Err(mistyped(name, CommandOptionType::String, other)),
+        }
+    }
+
+    fn schema(name: String, description: String, required: bool, autocomplete: bool) -> CommandOption {
+        CommandOption::String(ChoiceCommandOptionData {
+            autocomplete,
+            description,
+            name,
+            required,
+            ..Default::default()
+        })
+    }
+}
+
+impl FromOption for i64 {
+    fn from_option(name: &str, value: &CommandOptionValue, _: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        match value {
+            CommandOptionValue::Integer(value) => Ok(*value),
+            other => Err(mistyped(name, CommandOptionType::Integer, other)),
+        }
+    }
+
+    fn schema(name: String, description: String, required: bool, autocomplete: bool) -> CommandOption {
+        CommandOption::Integer(NumberCommandOptionData {
+            autocomplete,
+            description,
+            name,
+            required,
+            ..Default::default()
+        })
+    }
+}
+
+impl FromOption for f64 {
+    fn from_option(name: &str, value: &CommandOptionValue, _: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        match value {
+            CommandOptionValue::Number(value) => Ok(*value),
+            other => Err(mistyped(name, CommandOptionType::Number, other)),
+        }
+    }
+
+    fn schema(name: String, description: String, required: bool, autocomplete: bool) -> CommandOption {
+        CommandOption::Number(NumberCommandOptionData {
+            autocomplete,
+            description,
+            name,
+            required,
+            ..Default::default()
+        })
+    }
+}
+
+impl FromOption for bool {
+    fn from_option(name: &str, value: &CommandOptionValue, _: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        match value {
+            CommandOptionValue::Boolean(value) => Ok(*value),
+            other => Err(mistyped(name, CommandOptionType::Boolean, other)),
+        }
+    }
+
+    fn schema(name: String, description: String, required: bool, _: bool) -> CommandOption {
+        CommandOption::Boolean(base_data(name, description, required))
+    }
+}
+
+impl FromOption for User {
+    fn from_option(name: &str, value: &CommandOptionValue, resolved: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        User::resolve(name, value, resolved).cloned()
+    }
+
+    fn schema(name: String, description: String, required: bool, _: bool) -> CommandOption {
+        CommandOption::User(base_data(name, description, required))
+    }
+}
+
+impl FromOption for Role {
+    fn from_option(name: &str, value: &CommandOptionValue, resolved: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        Role::resolve(name, value, resolved).cloned()
+    }
+
+    fn schema(name: String, description: String, required: bool, _: bool) -> CommandOption {
+        CommandOption::Role(base_data(name, description, required))
+    }
+}
+
+impl FromOption for InteractionChannel {
+    fn from_option(name: &str, value: &CommandOptionValue, resolved: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        InteractionChannel::resolve(name, value, resolved).cloned()
+    }
+
+    fn schema(name: String, description: String, required: bool, _: bool) -> CommandOption {
+        CommandOption::Channel(ChannelCommandOptionData {
+            channel_types: Vec::new(),
+            description,
+            description_localizations: None,
+            name,
+            name_localizations: None,
+            required,
+        })
+    }
+}
+
+impl FromOption for Attachment {
+    fn from_option(name: &str, value: &CommandOptionValue, resolved: Option<&CommandInteractionDataResolved>) -> Result<Self, OptionError> {
+        Attachment::resolve(name, value, resolved).cloned()
+    }
+
+    fn schema(name: String, description: String, required: bool, _: bool) -> CommandOption {
+        CommandOption::Attachment(base_data(name, description, required))
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use worker::{Error, Result};
+use crate::util::ToOwnedString;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct CustomIdPattern {
+    starts_with: Option<String>,
+    equals: Option<String>,
+    template: Option<Vec<TemplateSegment>>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+enum TemplateSegment {
+    Literal(String),
+    Capture { name: String, kind: CaptureKind },
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+enum CaptureKind {
+    Str,
+    U64,
+    I64,
+}
+
+/// A value captured by a template [`CustomIdPattern`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomIdParam {
+    Str(String),
+    U64(u64),
+    I64(i64),
+}
+
+pub type CustomIdParams = HashMap<String, CustomIdParam>;
+
+impl CustomIdPattern {
+    pub fn starts_with<S: ToOwnedString>(pattern: S) -> Self {
+        Self {
+            starts_with: Some(pattern.to_owned_string()),
+            equals: None,
+            template: None,
+        }
+    }
+
+    pub fn equals<S: ToOwnedString>(custom_id: S) -> Self {
+        Self {
+            starts_with: None,
+            equals: Some(custom_id.to_owned_string()),
+            template: None,
+        }
+    }
+
+    /// Creates a pattern with named captures, e.g. `"gender:{name}"` or `"ticket:{id:u64}:close"`.
+    ///
+    /// Captures are strings unless typed as `u64` or `i64`. Two captures must be separated by a literal.
+    pub fn template<S: AsRef<str>>(template: S) -> Result<Self> {
+        let template = template.as_ref();
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            if let Some(start) = rest.strip_prefix('{') {
+                let end = start.find('}')
+                    .ok_or_else(|| Error::from(format!("Unclosed capture in custom_id template `{}`", template)))?;
+                let (name, kind) = match start[..end].split_once(':') {
+                    Some((name, "u64")) => (name, CaptureKind::U64),
+                    Some((name, "i64")) => (name, CaptureKind::I64),
+                    Some((name, "str")) => (name, CaptureKind::Str),
+                    Some((_, kind)) => return Err(Error::from(format!("Unknown capture type `{}` in custom_id template `{}`", kind, template))),
+                    None => (&start[..end], CaptureKind::Str),
+                };
+                if name.is_empty() {
+                    return Err(Error::from(format!("Unnamed capture in custom_id template `{}`", template)));
+                }
+                if let Some(TemplateSegment::Capture { .. }) = segments.last() {
+                    return Err(Error::from(format!("Adjacent captures in custom_id template `{}`", template)));
+                }
+                segments.push(TemplateSegment::Capture { name: name.to_string(), kind });
+                rest = &start[end + 1..];
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                segments.push(TemplateSegment::Literal(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+        Ok(Self {
+            starts_with: None,
+            equals: None,
+            template: Some(segments),
+        })
+    }
+
+    /// Ranks how specific this pattern is: exact matches first, then by the number of literal
+    /// characters, which is the prefix length for `starts_with` patterns.
+    fn specificity(&self) -> (u8, usize) {
+        if let Some(pattern) = self.starts_with.as_ref() {
+            (1, pattern.len())
+        } else if let Some(equals) = self.equals.as_ref() {
+            (2, equals.len())
+        } else if let Some(segments) = self.template.as_ref() {
+            (1, segments.iter()
+                .map(|segment| match segment {
+                    TemplateSegment::Literal(literal) => literal.len(),
+                    TemplateSegment::Capture { .. } => 0,
+                })
+                .sum())
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// Whether both patterns are equally specific and some custom_id matches both, in which
+    /// case only registration order would decide between them.
+    fn is_ambiguous_with(&self, other: &CustomIdPattern) -> bool {
+        self.specificity() == other.specificity()
+            && overlaps(&self.glob(), &other.glob(), &mut HashMap::new())
+    }
+
+    /// The pattern as a glob over the custom_id. Captures are untyped here, as matching ignores
+    /// their types.
+    fn glob(&self) -> Vec<GlobToken> {
+        let mut glob = Vec::new();
+        if let Some(pattern) = self.starts_with.as_ref() {
+            glob.extend(pattern.chars().map(GlobToken::Char));
+            glob.push(GlobToken::AnyString);
+        } else if let Some(equals) = self.equals.as_ref() {
+            glob.extend(equals.chars().map(GlobToken::Char));
+        } else if let Some(segments) = self.template.as_ref() {
+            for segment in segments {
+                match segment {
+                    TemplateSegment::Literal(literal) => glob.extend(literal.chars().map(GlobToken::Char)),
+                    // captures match at least one character
+                    TemplateSegment::Capture { .. } => glob.extend([GlobToken::AnyChar, GlobToken::AnyString]),
+                }
+            }
+        }
+        glob
+    }
+
+    pub(crate) fn matches(&self, custom_id: &str) -> bool {
+        if let Some(pattern) = self.starts_with.as_ref() {
+            custom_id.starts_with(pattern)
+        } else if let Some(equals) = self.equals.as_ref() {
+            custom_id.eq(equals)
+        } else if let Some(segments) = self.template.as_ref() {
+            match_segments(segments, custom_id, &mut Vec::new())
+        } else {
+            false
+        }
+    }
+
+    /// Parses the captures of a matching template pattern. Fails if a typed capture doesn't parse.
+    pub(crate) fn params(&self, custom_id: &str) -> Result<CustomIdParams> {
+        let segments = match self.template.as_ref() {
+            Some(segments) => segments,
+            None => return Ok(CustomIdParams::new())
+        };
+        let mut captures = Vec::new();
+        if !match_segments(segments, custom_id, &mut captures) {
+            return Ok(CustomIdParams::new());
+        }
+        captures.into_iter()
+            .map(|(name, kind, value)| {
+                let param = match kind {
+                    CaptureKind::Str => CustomIdParam::Str(value.to_string()),
+                    CaptureKind::U64 => CustomIdParam::U64(value.parse()
+                        .map_err(|err| Error::from(format!("Invalid value `{}` for `{}`: {}", value, name, err)))?),
+                    CaptureKind::I64 => CustomIdParam::I64(value.parse()
+                        .map_err(|err| Error::from(format!("Invalid value `{}` for `{}`: {}", value, name, err)))?),
+                };
+                Ok((name.to_string(), param))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GlobToken {
+    Char(char),
+    AnyChar,
+    AnyString,
+}
+
+/// Whether some string matches both globs. `seen` memoizes the positions already explored.
+fn overlaps(a: &[GlobToken], b: &[GlobToken], seen: &mut HashMap<(usize, usize), bool>) -> bool {
+    let key = (a.len(), b.len());
+    if let Some(result) = seen.get(&key) {
+        return *result;
+    }
+    let result = match (a.first(), b.first()) {
+        (None, None) => true,
+        // an AnyString either matches nothing more or absorbs the other side's next token
+        (Some(GlobToken::AnyString), _) => overlaps(&a[1..], b, seen) || (!b.is_empty() && overlaps(a, &b[1..], seen)),
+        (_, Some(GlobToken::AnyString)) => overlaps(a, &b[1..], seen) || (!a.is_empty() && overlaps(&a[1..], b, seen)),
+        (Some(GlobToken::Char(x)), Some(GlobToken::Char(y))) => x == y && overlaps(&a[1..], &b[1..], seen),
+        (Some(_), Some(_)) => overlaps(&a[1..], &b[1..], seen),
+        _ => false,
+    };
+    seen.insert(key, result);
+    result
+}
+
+fn match_segments<'a>(segments: &'a [TemplateSegment], input: &'a str, captures: &mut Vec<(&'a str, CaptureKind, &'a str)>) -> bool {
+    match segments.split_first() {
+        None => input.is_empty(),
+        Some((TemplateSegment::Literal(literal), rest)) => input.strip_prefix(literal.as_str())
+            .is_some_and(|input| match_segments(rest, input, captures)),
+        Some((TemplateSegment::Capture { name, kind }, rest)) => {
+            let literal = match rest.first() {
+                Some(TemplateSegment::Literal(literal)) => literal,
+                _ => {
+                    // the capture is the last segment and consumes the remaining input
+                    if input.is_empty() {
+                        return false;
+                    }
+                    captures.push((name, *kind, input));
+                    return true;
+                }
+            };
+            // the capture is non-empty and may end before any occurrence of the literal, even
+            // overlapping ones
+            let ends = input.char_indices().skip(1)
+                .map(|(index, _)| index)
+                .filter(|index| input[*index..].starts_with(literal.as_str()));
+            for index in ends {
+                captures.push((name, *kind, &input[..index]));
+                if match_segments(rest, &input[index..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+            false
+        }
+    }
+}
+
+/// Handlers keyed by [`CustomIdPattern`], matched by specificity and then by registration order.
+pub(crate) struct PatternMatcher<H> {
+    entries: Vec<(CustomIdPattern, H)>,
+}
+
+impl<H> PatternMatcher<H> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Registers `handler`, failing if `pattern` is ambiguous with an already registered pattern.
+    pub(crate) fn insert(&mut self, pattern: CustomIdPattern, handler: H) -> Result<()> {
+        if let Some((existing, _)) = self.entries.iter().find(|(existing, _)| existing.is_ambiguous_with(&pattern)) {
+            return Err(Error::from(format!("custom_id pattern {:?} is ambiguous with {:?}", pattern, existing)));
+        }
+        self.entries.push((pattern, handler));
+        Ok(())
+    }
+
+    /// Finds the most specific pattern matching `custom_id`, the earliest registered one on ties.
+    pub(crate) fn find(&self, custom_id: &str) -> Option<(&CustomIdPattern, &H)> {
+        self.entries.iter()
+            .filter(|(pattern, _)| pattern.matches(custom_id))
+            .fold(None, |best: Option<&(CustomIdPattern, H)>, entry| match best {
+                Some(best) if best.0.specificity() >= entry.0.specificity() => Some(best),
+                _ => Some(entry)
+            })
+            .map(|(pattern, handler)| (pattern, handler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(patterns: &[CustomIdPattern]) -> PatternMatcher<usize> {
+        let mut matcher = PatternMatcher::new();
+        for (index, pattern) in patterns.iter().enumerate() {
+            matcher.insert(pattern.clone(), index).unwrap();
+        }
+        matcher
+    }
+
+    fn found(matcher: &PatternMatcher<usize>, custom_id: &str) -> Option<usize> {
+        matcher.find(custom_id).map(|(_, handler)| *handler)
+    }
+
+    #[test]
+    fn exact_pattern_beats_prefixes() {
+        let matcher = matcher(&[
+            CustomIdPattern::starts_with("role"),
+            CustomIdPattern::equals("role-admin"),
+            CustomIdPattern::starts_with("role-"),
+        ]);
+        assert_eq!(found(&matcher, "role-admin"), Some(1));
+        assert_eq!(found(&matcher, "role-mod"), Some(2));
+        assert_eq!(found(&matcher, "roles"), Some(0));
+        assert_eq!(found(&matcher, "other"), None);
+    }
+
+    #[test]
+    fn longest_literal_wins_between_prefix_and_template() {
+        let matcher = matcher(&[
+            CustomIdPattern::starts_with("ticket"),
+            CustomIdPattern::template("ticket:{id:u64}:close").unwrap(),
+        ]);
+        assert_eq!(found(&matcher, "ticket:1:close"), Some(1));
+        assert_eq!(found(&matcher, "ticket:1:open"), Some(0));
+    }
+
+    #[test]
+    fn registration_order_breaks_ties() {
+        // overlapping patterns of equal specificity are rejected by insert, so build the tie directly
+        let matcher = PatternMatcher {
+            entries: vec![
+                (CustomIdPattern::starts_with("ab"), 0),
+                (CustomIdPattern::starts_with("ac"), 1),
+                (CustomIdPattern::starts_with("a-"), 2),
+                (CustomIdPattern::starts_with("ab"), 3),
+            ],
+        };
+        assert_eq!(found(&matcher, "abc"), Some(0));
+    }
+
+    #[test]
+    fn template_rejects_malformed_captures() {
+        assert!(CustomIdPattern::template("ticket:{id").is_err());
+        assert!(CustomIdPattern::template("ticket:{}").is_err());
+        assert!(CustomIdPattern::template("ticket:{:u64}").is_err());
+        assert!(CustomIdPattern::template("ticket:{a}{b}").is_err());
+        assert!(CustomIdPattern::template("ticket:{id:u32}").is_err());
+        assert!(CustomIdPattern::template("ticket:{id:u64}:{action}").is_ok());
+    }
+
+    #[test]
+    fn template_backtracks_to_later_separators() {
+        let pattern = CustomIdPattern::template("{name}:end").unwrap();
+        assert!(pattern.matches("a:end:x:end"));
+        let params = pattern.params("a:end:x:end").unwrap();
+        assert_eq!(params.get("name"), Some(&CustomIdParam::Str("a:end:x".to_string())));
+        assert!(!pattern.matches(":end"));
+        assert!(!pattern.matches("a:end:x"));
+        let pattern = CustomIdPattern::template("{x}::").unwrap();
+        assert_eq!(pattern.params("a:::").unwrap().get("x"), Some(&CustomIdParam::Str("a:".to_string())));
+        let pattern = CustomIdPattern::template("{x}aa").unwrap();
+        assert_eq!(pattern.params("xaaa").unwrap().get("x"), Some(&CustomIdParam::Str("xa".to_string())));
+        let pattern = CustomIdPattern::template("{x}aa:{y}").unwrap();
+        let params = pattern.params("baaa:c").unwrap();
+        assert_eq!(params.get("x"), Some(&CustomIdParam::Str("ba".to_string())));
+        assert_eq!(params.get("y"), Some(&CustomIdParam::Str("c".to_string())));
+        let pattern = CustomIdPattern::template("page:{index:u64}").unwrap();
+        assert_eq!(pattern.params("page:42").unwrap().get("index"), Some(&CustomIdParam::U64(42)));
+        assert!(!pattern.matches("page:"));
+    }
+
+    #[test]
+    fn typed_captures_fail_to_parse() {
+        let pattern = CustomIdPattern::template("ticket:{id:u64}:close").unwrap();
+        assert!(pattern.matches("ticket:abc:close"));
+        assert!(pattern.params("ticket:abc:close").is_err());
+        assert!(pattern.params("ticket:-1:close").is_err());
+        let pattern = CustomIdPattern::template("offset:{by:i64}").unwrap();
+        assert_eq!(pattern.params("offset:-1").unwrap().get("by"), Some(&CustomIdParam::I64(-1)));
+    }
+
+    #[test]
+    fn disjoint_templates_are_not_ambiguous() {
+        let open = CustomIdPattern::template("ticket:{id:u64}:open").unwrap();
+        let edit = CustomIdPattern::template("ticket:{id:u64}:edit").unwrap();
+        assert!(!open.is_ambiguous_with(&edit));
+        let mut matcher = PatternMatcher::new();
+        matcher.insert(open, 0).unwrap();
+        matcher.insert(edit, 1).unwrap();
+        assert_eq!(found(&matcher, "ticket:7:edit"), Some(1));
+        assert!(!CustomIdPattern::starts_with("ab").is_ambiguous_with(&CustomIdPattern::starts_with("ac")));
+        assert!(!CustomIdPattern::equals("a").is_ambiguous_with(&CustomIdPattern::equals("b")));
+    }
+
+    #[test]
+    fn overlapping_patterns_are_ambiguous() {
+        let mut matcher = PatternMatcher::new();
+        matcher.insert(CustomIdPattern::starts_with("page"), 0).unwrap();
+        assert!(matcher.insert(CustomIdPattern::starts_with("page"), 1).is_err());
+        assert!(matcher.insert(CustomIdPattern::starts_with("pa-e"), 1).is_ok());
+        assert!(CustomIdPattern::equals("a").is_ambiguous_with(&CustomIdPattern::equals("a")));
+        assert!(CustomIdPattern::starts_with("ab").is_ambiguous_with(&CustomIdPattern::template("a{x}b").unwrap()));
+        assert!(CustomIdPattern::template("{a}:x").unwrap()
+            .is_ambiguous_with(&CustomIdPattern::template("x:{b}").unwrap()));
+    }
+}
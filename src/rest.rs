@@ -1,11 +1,14 @@
 use crate::{MessageBuilder, ToOwnedString};
-use reqwest::{Client as HttpClient, Method, Response as HttpResponse};
+use reqwest::{Client as HttpClient, Method, RequestBuilder, Response as HttpResponse};
+use serde::Serialize;
 use serde::de::DeserializeOwned;
+use twilight_model::application::command::Command;
 use twilight_model::channel::Message;
 use twilight_model::channel::message::MessageFlags;
 use twilight_model::guild::member::MemberIntermediary;
+use twilight_model::http::interaction::InteractionResponseData;
 use worker::*;
-use crate::model::MemberEditBuilder;
+use crate::model::{CommandBuilder, MemberEditBuilder};
 
 macro_rules! handle_errors {
     ($req:expr) => {
@@ -129,6 +132,21 @@ impl Client {
         ).await.map(|_| ())
     }
 
+    /// Fetches the global commands of the application, or its commands in `guild_id`.
+    pub async fn get_commands(&self, app_id: &u64, guild_id: Option<&u64>) -> Result<Vec<Command>> {
+        self.request_json(
+            Method::GET,
+            format!("{}?with_localizations=true", commands_url(app_id, guild_id)).as_str(),
+        ).await
+    }
+
+    /// Replaces the global commands of the application, or its commands in `guild_id`, with `commands`.
+    pub async fn bulk_overwrite_commands(&self, app_id: &u64, guild_id: Option<&u64>, commands: &[CommandBuilder]) -> Result<Vec<Command>> {
+        self.request_with_body::<_, &str>(Method::PUT, commands_url(app_id, guild_id).as_str(), commands, None).await?
+            .json().await
+            .map_err(crate::util::map_error)
+    }
+
     pub async fn request_json<T: DeserializeOwned>(&self, method: Method, path: &str) -> Result<T> {
         self.request::<&str>(method, path, None).await?
             .json().await
@@ -136,22 +154,44 @@ impl Client {
     }
 
     pub async fn request<S: ToOwnedString>(&self, method: Method, path: &str, audit_log_reason: Option<S>) -> Result<HttpResponse> {
+        send(self.request_builder(method, path, audit_log_reason)).await
+    }
+
+    /// Like [`Client::request`], sending `body` as JSON.
+    pub async fn request_with_body<B: Serialize + ?Sized, S: ToOwnedString>(&self, method: Method, path: &str, body: &B,
+                                                                            audit_log_reason: Option<S>) -> Result<HttpResponse> {
+        send(self.request_builder(method, path, audit_log_reason).json(body)).await
+    }
+
+    fn request_builder<S: ToOwnedString>(&self, method: Method, path: &str, audit_log_reason: Option<S>) -> RequestBuilder {
         let mut request_builder = self.client.request(method, path)
             .header("Authorization", format!("Bot {}", self.token.as_str()));
         if let Some(reason) = audit_log_reason {
             request_builder = request_builder
                 .header("X-Audit-Log-Reason", reason.to_owned_string());
         }
-        match request_builder.send().await {
-            Ok(res) => {
-                if res.status().is_success() {
-                    Ok(res)
-                } else {
-                    Err(Error::from(res.text().await.expect("Failed to get response body")))
-                }
+        request_builder
+    }
+}
+
+/// Sends the request, turning unsuccessful responses into an error carrying the response body.
+async fn send(request_builder: RequestBuilder) -> Result<HttpResponse> {
+    match request_builder.send().await {
+        Ok(res) => {
+            if res.status().is_success() {
+                Ok(res)
+            } else {
+                Err(Error::from(res.text().await.map_err(|err| Error::from(err.to_string()))?))
             }
-            Err(err) => Err(Error::from(err.to_string())),
         }
+        Err(err) => Err(Error::from(err.to_string())),
+    }
+}
+
+fn commands_url(app_id: &u64, guild_id: Option<&u64>) -> String {
+    match guild_id {
+        Some(guild_id) => format!("https://discord.com/api/applications/{}/guilds/{}/commands", app_id, guild_id),
+        None => format!("https://discord.com/api/applications/{}/commands", app_id),
     }
 }
 
@@ -175,4 +215,31 @@ impl RestInteraction {
             .await
             .map_err(|err| Error::from(err.to_string()))
     }
-}
\ No newline at end of file
+
+    pub async fn edit_original<F: FnOnce(&mut MessageBuilder)>(&self, message_builder: F) -> Result<Message> {
+        let mut builder = MessageBuilder::default();
+        message_builder(&mut builder);
+        self.edit_original_response(&InteractionResponseData::from(builder)).await
+    }
+
+    /// Posts `data` as a followup message, made ephemeral if the interaction was deferred ephemerally.
+    pub async fn followup_response(&self, data: &InteractionResponseData) -> Result<Message> {
+        let mut data = data.clone();
+        if self.ephemeral {
+            data.flags = Some(data.flags.unwrap_or_else(MessageFlags::empty) | MessageFlags::EPHEMERAL);
+        }
+        send(self.client.request(Method::POST, format!("https://discord.com/api/webhooks/{}/{}", self.app_id, self.token))
+            .json(&data)).await?
+            .json()
+            .await
+            .map_err(|err| Error::from(err.to_string()))
+    }
+
+    pub async fn edit_original_response(&self, data: &InteractionResponseData) -> Result<Message> {
+        send(self.client.request(Method::PATCH, format!("https://discord.com/api/webhooks/{}/{}/messages/@original", self.app_id, self.token))
+            .json(data)).await?
+            .json()
+            .await
+            .map_err(|err| Error::from(err.to_string()))
+    }
+}
@@ -1,7 +1,12 @@
-use ed25519_dalek::{PUBLIC_KEY_LENGTH, PublicKey, Signature, SIGNATURE_LENGTH, Verifier};
+use std::time::Duration;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use twilight_model::application::command::CommandOptionChoice;
+use twilight_model::application::interaction::InteractionType;
 use twilight_model::channel::message::MessageFlags;
 use twilight_model::http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType};
 use worker::*;
+use crate::{InteractionContext, InteractionError};
+use crate::model::MessageBuilder;
 
 pub trait ToOwnedString {
     fn to_owned_string(self) -> String;
@@ -23,34 +28,62 @@ pub fn map_error<T: ToString>(error: T) -> Error {
     Error::RustError(error.to_string())
 }
 
-pub fn validate_headers<S: AsRef<str>>(req: &Request, body: &[u8], public_key: S) -> Result<bool> {
-    let sig = req.headers().get("x-signature-ed25519")?;
-    let timestamp = req.headers().get("x-signature-timestamp")?;
-    if sig.is_none() || timestamp.is_none() {
-        return Ok(false);
+/// Parses a hex encoded ed25519 public key, as shown in the Discord developer portal.
+pub fn parse_public_key<S: AsRef<str>>(public_key: S) -> Result<PublicKey> {
+    let bytes = hex::decode(public_key.as_ref().trim())
+        .map_err(|err| Error::from(format!("Public key is not hex encoded: {}", err)))?;
+    PublicKey::from_bytes(bytes.as_slice())
+        .map_err(|err| Error::from(format!("Invalid public key: {}", err)))
+}
+
+/// Verifies the ed25519 signature of the request. Missing or malformed headers and invalid
+/// signatures are logged and fail the validation.
+pub fn validate_headers(req: &Request, body: &[u8], public_key: &PublicKey) -> bool {
+    let header = |name: &str| req.headers().get(name).ok().flatten();
+    let (sig, timestamp) = match (header("x-signature-ed25519"), header("x-signature-timestamp")) {
+        (Some(sig), Some(timestamp)) => (sig, timestamp),
+        _ => {
+            console_log!("Rejected request: missing signature headers");
+            return false;
+        }
+    };
+    if !verify_signature(sig.as_str(), timestamp.as_str(), body, public_key) {
+        console_log!("Rejected request: invalid or malformed signature `{}`", sig);
+        return false;
     }
-    let sig = sig.unwrap();
-    let timestamp = timestamp.unwrap();
+    true
+}
 
-    let public_key_hex = hex::decode(public_key.as_ref())
-        .map_err(|err| Error::from(err.to_string()))?;
-    let signature_hex = hex::decode(sig)
-        .map_err(|err| Error::from(err.to_string()))?;
-    let public_key = PublicKey::from_bytes(&public_key_hex.as_slice()[..PUBLIC_KEY_LENGTH]).unwrap();
-    let signature = Signature::from_bytes(&signature_hex.as_slice()[..SIGNATURE_LENGTH]).unwrap();
+/// Verifies the hex encoded ed25519 signature `sig` over `timestamp` followed by `body`.
+/// Malformed signatures fail the verification.
+pub fn verify_signature(sig: &str, timestamp: &str, body: &[u8], public_key: &PublicKey) -> bool {
+    let signature = match hex::decode(sig).ok()
+        .and_then(|bytes| Signature::from_bytes(bytes.as_slice()).ok()) {
+        Some(signature) => signature,
+        None => return false
+    };
 
-    let mut full_body = timestamp.into_bytes();
+    let mut full_body = timestamp.as_bytes().to_vec();
     full_body.extend_from_slice(body);
 
-    if let Err(err) = public_key.verify(full_body.as_slice(), &signature) {
-        console_log!("Received invalid signature: {}", err);
-        Ok(false)
-    } else {
-        Ok(true)
+    public_key.verify(full_body.as_slice(), &signature).is_ok()
+}
+
+/// Returns why the `x-signature-timestamp` `header` is rejected at `now_secs`, if it is more than
+/// `max_age` away in either direction or not a number of seconds.
+pub(crate) fn timestamp_rejection(header: &str, now_secs: u64, max_age: Duration) -> Option<String> {
+    let timestamp = match header.parse::<u64>() {
+        Ok(timestamp) => timestamp,
+        Err(_) => return Some(format!("Malformed timestamp `{}`", header))
+    };
+    let age = now_secs.abs_diff(timestamp);
+    if age > max_age.as_secs() {
+        return Some(format!("Timestamp is {} seconds off", age));
     }
+    None
 }
 
-pub(crate) fn error_message(message: String) -> InteractionResponse {
+pub fn error_message(message: String) -> InteractionResponse {
     InteractionResponse {
         kind: InteractionResponseType::ChannelMessageWithSource,
         data: Some(InteractionResponseData {
@@ -66,4 +99,127 @@ pub(crate) fn error_message(message: String) -> InteractionResponse {
             tts: None,
         }),
     }
-}
\ No newline at end of file
+}
+
+pub(crate) fn autocomplete_result(choices: Vec<CommandOptionChoice>) -> InteractionResponse {
+    InteractionResponse {
+        kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+        data: Some(InteractionResponseData {
+            allowed_mentions: None,
+            attachments: None,
+            choices: Some(choices),
+            components: None,
+            content: None,
+            custom_id: None,
+            embeds: None,
+            flags: None,
+            title: None,
+            tts: None,
+        }),
+    }
+}
+
+/// Shows user errors as an ephemeral message. Internal errors are logged and answered with a
+/// generic message. Autocomplete interactions get no choices.
+pub fn default_error_response<S>(error: &InteractionError, context: &InteractionContext<(), S>) -> InteractionResponse {
+    if let InteractionError::Internal(err) = error {
+        console_log!("Interaction {} failed: {}", context.raw.id, err);
+    }
+    if context.raw.kind == InteractionType::ApplicationCommandAutocomplete {
+        return autocomplete_result(Vec::new());
+    }
+    match error {
+        InteractionError::User(builder) => {
+            let mut builder = MessageBuilder::clone(builder);
+            builder.flags = Some(builder.flags.unwrap_or_else(MessageFlags::empty) | MessageFlags::EPHEMERAL);
+            InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(InteractionResponseData::from(builder)),
+            }
+        }
+        InteractionError::Internal(_) => error_message("An error occurred while processing this interaction".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{ExpandedSecretKey, SecretKey};
+    use super::*;
+
+    const TIMESTAMP: &str = "1700000000";
+    const BODY: &[u8] = br#"{"type":1}"#;
+
+    fn keys() -> (ExpandedSecretKey, PublicKey) {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        (ExpandedSecretKey::from(&secret), PublicKey::from(&secret))
+    }
+
+    fn sign(timestamp: &str, body: &[u8]) -> (String, PublicKey) {
+        let (secret, public) = keys();
+        let mut message = timestamp.as_bytes().to_vec();
+        message.extend_from_slice(body);
+        (hex::encode(secret.sign(message.as_slice(), &public).to_bytes()), public)
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let (sig, public_key) = sign(TIMESTAMP, BODY);
+        assert!(verify_signature(sig.as_str(), TIMESTAMP, BODY, &public_key));
+    }
+
+    #[test]
+    fn rejects_signature_over_other_content() {
+        let (sig, public_key) = sign(TIMESTAMP, BODY);
+        assert!(!verify_signature(sig.as_str(), "1700000001", BODY, &public_key));
+        assert!(!verify_signature(sig.as_str(), TIMESTAMP, br#"{"type":2}"#, &public_key));
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        let (sig, public_key) = sign(TIMESTAMP, BODY);
+        let malformed = [
+            String::new(),
+            "ab".to_string(),
+            sig[..sig.len() - 1].to_string(),
+            format!("zz{}", &sig[2..]),
+            sig[..126].to_string(),
+            format!("{}00", sig),
+        ];
+        for sig in malformed {
+            assert!(!verify_signature(sig.as_str(), TIMESTAMP, BODY, &public_key), "accepted `{}`", sig);
+        }
+    }
+
+    #[test]
+    fn accepts_timestamps_within_max_age() {
+        let max_age = Duration::from_secs(5);
+        assert_eq!(timestamp_rejection(TIMESTAMP, 1_700_000_000, max_age), None);
+        assert_eq!(timestamp_rejection(TIMESTAMP, 1_700_000_005, max_age), None);
+        assert_eq!(timestamp_rejection(TIMESTAMP, 1_699_999_995, max_age), None);
+    }
+
+    #[test]
+    fn rejects_timestamps_beyond_max_age() {
+        let max_age = Duration::from_secs(5);
+        assert!(timestamp_rejection(TIMESTAMP, 1_700_000_006, max_age).is_some());
+        assert!(timestamp_rejection(TIMESTAMP, 1_699_999_994, max_age).is_some());
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        for header in ["", "-1", "1700000000.5", "soon"] {
+            assert!(timestamp_rejection(header, 1_700_000_000, Duration::from_secs(5)).is_some(), "accepted `{}`", header);
+        }
+    }
+
+    #[test]
+    fn parses_public_key() {
+        let (_, public_key) = keys();
+        let encoded = hex::encode(public_key.as_bytes());
+        assert_eq!(parse_public_key(encoded.as_str()).unwrap(), public_key);
+        assert!(parse_public_key("not hex").is_err());
+        assert!(parse_public_key(&encoded[..encoded.len() - 1]).is_err());
+        assert!(parse_public_key(&encoded[..62]).is_err());
+        assert!(parse_public_key(format!("{}00", encoded)).is_err());
+    }
+}
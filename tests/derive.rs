@@ -0,0 +1,116 @@
+use serde_json::json;
+use worker_wasm_interactions_rs::{CommandChoice, CommandOptions};
+use worker_wasm_interactions_rs::options::{CommandOptions as _, OptionError};
+use worker_wasm_interactions_rs::twilight_model::application::command::{CommandOption, CommandOptionChoice};
+use worker_wasm_interactions_rs::twilight_model::application::interaction::application_command::CommandData;
+use worker_wasm_interactions_rs::twilight_model::user::User;
+
+#[derive(Debug, PartialEq, CommandChoice)]
+enum Gender {
+    Male,
+    Female,
+    #[choice(name = "Non-Binary", value = "non-binary")]
+    NonBinary,
+}
+
+#[derive(Debug, CommandOptions)]
+struct GenderOptions {
+    #[option(description = "Whether the response should be hidden")]
+    hidden: Option<bool>,
+    /// The gender role to toggle
+    gender: Gender,
+    #[option(name = "target", description = "The member to toggle the role for")]
+    user: User,
+    note: Option<String>,
+}
+
+fn command_data(options: serde_json::Value) -> CommandData {
+    serde_json::from_value(json!({
+        "id": "1",
+        "name": "gender",
+        "type": 1,
+        "options": options,
+        "resolved": {
+            "users": {
+                "42": { "id": "42", "username": "someone", "discriminator": "0001", "avatar": null }
+            }
+        }
+    })).unwrap()
+}
+
+#[test]
+fn parses_required_optional_choice_and_resolved_fields() {
+    let data = command_data(json!([
+        { "name": "gender", "type": 3, "value": "non-binary" },
+        { "name": "target", "type": 6, "value": "42" },
+        { "name": "hidden", "type": 5, "value": false },
+    ]));
+    let options = GenderOptions::from_command_data(&data).unwrap();
+    assert_eq!(options.hidden, Some(false));
+    assert_eq!(options.gender, Gender::NonBinary);
+    assert_eq!(options.user.name, "someone");
+    assert_eq!(options.note, None);
+}
+
+#[test]
+fn reports_missing_and_invalid_options() {
+    let data = command_data(json!([{ "name": "target", "type": 6, "value": "42" }]));
+    assert_eq!(GenderOptions::from_command_data(&data).unwrap_err(), OptionError::Missing { name: "gender".to_string() });
+
+    let data = command_data(json!([
+        { "name": "gender", "type": 3, "value": "other" },
+        { "name": "target", "type": 6, "value": "42" },
+    ]));
+    assert_eq!(GenderOptions::from_command_data(&data).unwrap_err(), OptionError::InvalidChoice {
+        name: "gender".to_string(),
+        value: "other".to_string(),
+    });
+
+    let data = command_data(json!([
+        { "name": "gender", "type": 3, "value": "male" },
+        { "name": "target", "type": 6, "value": "43" },
+    ]));
+    assert_eq!(GenderOptions::from_command_data(&data).unwrap_err(), OptionError::Unresolved { name: "target".to_string() });
+}
+
+#[test]
+fn lists_required_options_first() {
+    let options = GenderOptions::command_options();
+    let names: Vec<&str> = options.iter().map(option_name).collect();
+    assert_eq!(names, ["gender", "target", "hidden", "note"]);
+
+    match &options[0] {
+        CommandOption::String(data) => {
+            assert!(data.required);
+            assert_eq!(data.description, "The gender role to toggle");
+            assert_eq!(data.choices, vec![
+                CommandOptionChoice::String { name: "Male".to_string(), name_localizations: None, value: "male".to_string() },
+                CommandOptionChoice::String { name: "Female".to_string(), name_localizations: None, value: "female".to_string() },
+                CommandOptionChoice::String { name: "Non-Binary".to_string(), name_localizations: None, value: "non-binary".to_string() },
+            ]);
+        }
+        other => panic!("Expected a string option, got {:?}", other),
+    }
+    match &options[1] {
+        CommandOption::User(data) => {
+            assert!(data.required);
+            assert_eq!(data.description, "The member to toggle the role for");
+        }
+        other => panic!("Expected a user option, got {:?}", other),
+    }
+    match &options[3] {
+        CommandOption::String(data) => {
+            assert!(!data.required);
+            assert_eq!(data.description, "note");
+        }
+        other => panic!("Expected a string option, got {:?}", other),
+    }
+}
+
+fn option_name(option: &CommandOption) -> &str {
+    match option {
+        CommandOption::String(data) => &data.name,
+        CommandOption::User(data) | CommandOption::Boolean(data) => &data.name,
+        other => panic!("Unexpected option {:?}", other),
+    }
+}